@@ -0,0 +1,489 @@
+//! `ova agent` keeps stores in memory and hands out codes over a Unix domain
+//! socket, so hotkeys don't have to load the store every time.
+//!
+//! A client sends a single line of tab separated fields and reads the answer
+//! until the agent closes the connection. The first line of the answer is
+//! `ok`, `notfound` or `error`, followed by the code, the key names one per
+//! line, or the error message. Store paths must be absolute, the agent
+//! doesn't share the working directory of its clients.
+//!
+//! ```text
+//! code <TAB> digits <TAB> store path <TAB> name [<TAB> time offset]
+//! names <TAB> store path
+//! ping
+//! ```
+
+use crate::{
+    error::{Context, Error, Result},
+    format::Entry,
+    otp::{OffsetClock, SystemClock},
+    store::Store,
+    utils::get_entry_code,
+};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+pub const DEFAULT_AGENT_TIMEOUT: Duration = Duration::from_secs(600);
+
+const AGENT_SOCK_VAR: &str = "OVA_AGENT_SOCK";
+
+const SOCKET_FILE_NAME: &str = "ova-agent.sock";
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct CachedStore {
+    modified: Option<SystemTime>,
+    entries: HashMap<String, Entry>,
+}
+
+pub struct Agent {
+    listener: UnixListener,
+    socket_path: PathBuf,
+    timeout: Duration,
+    stores: HashMap<PathBuf, CachedStore>,
+    last_used: Instant,
+}
+
+impl Agent {
+    /// Listens on `socket_path`, replacing a socket left behind by an agent
+    /// that is gone
+    pub fn bind(socket_path: &Path, timeout: Duration) -> Result<Self> {
+        if socket_path.exists() {
+            if UnixStream::connect(socket_path).is_ok() {
+                return Err(Error::Agent(format!(
+                    "An agent is already listening on {}",
+                    socket_path.to_string_lossy()
+                )));
+            }
+            fs::remove_file(socket_path).with_context(|| {
+                format!(
+                    "Error removing stale socket - {}",
+                    socket_path.to_string_lossy()
+                )
+            })?;
+        }
+
+        if let Some(dir) = socket_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            crate::permissions::create_private_dir(dir)?;
+        }
+
+        let listener = UnixListener::bind(socket_path).with_context(|| {
+            format!(
+                "Error listening on socket - {}",
+                socket_path.to_string_lossy()
+            )
+        })?;
+
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600)).with_context(
+                || {
+                    format!(
+                        "Error changing permissions of - {}",
+                        socket_path.to_string_lossy()
+                    )
+                },
+            )?;
+        }
+
+        Ok(Agent {
+            listener,
+            socket_path: socket_path.to_path_buf(),
+            timeout,
+            stores: HashMap::new(),
+            last_used: Instant::now(),
+        })
+    }
+
+    /// `$XDG_RUNTIME_DIR/ova-agent.sock`, falling back to a directory of the
+    /// user in the temp dir
+    pub fn default_socket_path() -> PathBuf {
+        match env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join(SOCKET_FILE_NAME),
+            _ => env::temp_dir()
+                .join(format!("ova-{}", unsafe { libc::geteuid() }))
+                .join(SOCKET_FILE_NAME),
+        }
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Answers requests until the process is stopped, forgetting the cached
+    /// stores once nobody asked for a code for the idle timeout
+    pub fn serve(&mut self) -> Result<()> {
+        loop {
+            self.serve_once(None)?;
+        }
+    }
+
+    /// Waits for one connection, at most `wait` when given, and answers it
+    fn serve_once(&mut self, wait: Option<Duration>) -> Result<()> {
+        let idle = self.timeout.saturating_sub(self.last_used.elapsed());
+        let wait = if self.stores.is_empty() {
+            wait
+        } else {
+            Some(wait.map_or(idle, |wait| wait.min(idle)))
+        };
+
+        if !self.poll(wait)? {
+            if self.last_used.elapsed() >= self.timeout {
+                self.stores.clear();
+            }
+            return Ok(());
+        }
+
+        let (stream, _) = self
+            .listener
+            .accept()
+            .with_context(|| "Error accepting a connection")?;
+        if let Err(err) = self.handle(stream) {
+            eprintln!("Error: {}", err);
+        }
+
+        Ok(())
+    }
+
+    /// Whether a connection is waiting, blocking for at most `wait`
+    fn poll(&self, wait: Option<Duration>) -> Result<bool> {
+        let mut fd = libc::pollfd {
+            fd: self.listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Rounded up, waking up a little early would leave the stores cached
+        // until the next request
+        let timeout = wait.map_or(-1, |wait| {
+            wait.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32
+        });
+
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            -1 => {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::Interrupted {
+                    Ok(false)
+                } else {
+                    Err(err).with_context(|| "Error waiting for connections")
+                }
+            }
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    fn handle(&mut self, mut stream: UnixStream) -> Result<()> {
+        stream
+            .set_read_timeout(Some(CLIENT_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)))
+            .with_context(|| "Error setting up a connection")?;
+
+        let uid = peer_uid(&stream).with_context(|| "Error reading the peer of a connection")?;
+        let own_uid = unsafe { libc::geteuid() };
+        if uid != own_uid {
+            let _ = stream.write_all(b"error\nPermission denied\n");
+            return Err(Error::Agent(format!("Refused connection from uid {}", uid)));
+        }
+
+        // Connections closed without a request come from checks whether the
+        // agent is running
+        let mut request = String::new();
+        let read = BufReader::new(&stream)
+            .read_line(&mut request)
+            .with_context(|| "Error reading a request")?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        let response = match self.respond(request.trim_end_matches('\n')) {
+            Ok(body) => format!("ok\n{}", body),
+            Err(Error::KeyNotFound(names)) => format!("notfound\n{}\n", names.join("\n")),
+            Err(err) => format!("error\n{}\n", err),
+        };
+
+        stream
+            .write_all(response.as_bytes())
+            .with_context(|| "Error sending a response")
+    }
+
+    fn respond(&mut self, request: &str) -> Result<String> {
+        let fields: Vec<&str> = request.split('\t').collect();
+
+        match fields.as_slice() {
+            ["code", digits, path, name, offset @ ..] if offset.len() <= 1 => {
+                let digits: usize = digits
+                    .parse()
+                    .map_err(|_| Error::Agent("Invalid digits".to_string()))?;
+                let clock = OffsetClock {
+                    clock: SystemClock,
+                    offset: offset
+                        .first()
+                        .map_or(Ok(0), |offset| offset.parse())
+                        .map_err(|_| Error::Agent("Invalid time offset".to_string()))?,
+                };
+                let entry = self
+                    .entries(Path::new(path))?
+                    .get(*name)
+                    .ok_or_else(|| Error::KeyNotFound(vec![name.to_string()]))?;
+
+                Ok(format!("{}\n", get_entry_code(entry, digits, &clock)?))
+            }
+            ["names", path] => {
+                let mut names: Vec<&String> = self
+                    .entries(Path::new(path))?
+                    .keys()
+                    .filter(|name| !name.contains(|c: char| c.is_control()))
+                    .collect();
+                names.sort();
+
+                Ok(names.iter().map(|name| format!("{}\n", name)).collect())
+            }
+            ["ping"] => Ok(String::new()),
+            _ => Err(Error::Agent("Invalid request".to_string())),
+        }
+    }
+
+    /// Entries of the store at `path` by name, loaded again when the file
+    /// changed since it was cached
+    fn entries(&mut self, path: &Path) -> Result<&HashMap<String, Entry>> {
+        self.last_used = Instant::now();
+        if !path.is_absolute() {
+            return Err(Error::Agent(format!(
+                "Store path must be absolute - {}",
+                path.to_string_lossy()
+            )));
+        }
+        let modified = fs::metadata(path)
+            .with_context(|| format!("Error reading store at - {}", path.to_string_lossy()))?
+            .modified()
+            .ok();
+
+        let stale = self
+            .stores
+            .get(path)
+            .is_none_or(|cached| cached.modified.is_none() || cached.modified != modified);
+        if stale {
+            let store = Store::open(path)?;
+            self.stores.insert(
+                path.to_path_buf(),
+                CachedStore {
+                    modified,
+                    entries: store
+                        .entries()?
+                        .into_iter()
+                        .map(|entry| (entry.name.clone(), entry))
+                        .collect(),
+                },
+            );
+        }
+
+        Ok(&self.stores[path].entries)
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket_path);
+    }
+}
+
+/// Talks to the agent named by `OVA_AGENT_SOCK`
+pub struct AgentClient {
+    socket_path: PathBuf,
+}
+
+impl AgentClient {
+    pub fn from_env() -> Option<Self> {
+        env::var_os(AGENT_SOCK_VAR)
+            .filter(|path| !path.is_empty())
+            .map(|path| AgentClient {
+                socket_path: PathBuf::from(path),
+            })
+    }
+
+    /// Whether an agent answers on the socket
+    pub fn ping(&self) -> bool {
+        self.request("ping").is_ok()
+    }
+
+    pub fn code(
+        &self,
+        store_path: &Path,
+        name: &str,
+        digits: usize,
+        time_offset: i64,
+    ) -> Result<String> {
+        let mut request = format!(
+            "code\t{}\t{}\t{}",
+            digits,
+            store_path.to_string_lossy(),
+            name
+        );
+        // Left out when there's none, agents started before offsets existed
+        // keep working
+        if time_offset != 0 {
+            request.push_str(&format!("\t{}", time_offset));
+        }
+
+        let body = self.request(&request)?;
+
+        Ok(body.trim().to_string())
+    }
+
+    pub fn names(&self, store_path: &Path) -> Result<Vec<String>> {
+        let body = self.request(&format!("names\t{}", store_path.to_string_lossy()))?;
+
+        Ok(body.lines().map(|name| name.to_string()).collect())
+    }
+
+    fn request(&self, request: &str) -> Result<String> {
+        let mut stream = UnixStream::connect(&self.socket_path).with_context(|| {
+            format!(
+                "Error connecting to the agent at - {}",
+                self.socket_path.to_string_lossy()
+            )
+        })?;
+        let mut response = String::new();
+        stream
+            .set_read_timeout(Some(CLIENT_TIMEOUT))
+            .and_then(|_| stream.write_all(format!("{}\n", request).as_bytes()))
+            .and_then(|_| stream.read_to_string(&mut response))
+            .with_context(|| {
+                format!(
+                    "Error talking to the agent at - {}",
+                    self.socket_path.to_string_lossy()
+                )
+            })?;
+        let (status, body) = response.split_once('\n').unwrap_or((&response, ""));
+
+        match status {
+            "ok" => Ok(body.to_string()),
+            "notfound" => Err(Error::KeyNotFound(
+                body.lines().map(|name| name.to_string()).collect(),
+            )),
+            "error" => Err(Error::Agent(format!("Agent: {}", body.trim()))),
+            _ => Err(Error::Agent("Invalid response from the agent".to_string())),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(credentials.uid)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut uid: libc::uid_t = 0;
+    let mut gid: libc::gid_t = 0;
+
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(uid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{otp::Otp, secret::Secret, utils::get_2fa_code};
+    use std::thread;
+
+    #[test]
+    fn test_agent_hands_out_codes() {
+        let socket_path = PathBuf::new().join("test_agent.sock");
+        let store_path = env::current_dir().unwrap().join("test_agent.store");
+        fs::write(
+            &store_path,
+            "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\naws = MFRGGZDFMZTWQ2LKNNWG23TPOA\n\
+             steam = GEZDGNBVGY3TQOJQGEZDGNBVGY\n    type = steam\n",
+        )
+        .unwrap();
+
+        let mut agent = Agent::bind(&socket_path, DEFAULT_AGENT_TIMEOUT).unwrap();
+        assert!(Agent::bind(&socket_path, DEFAULT_AGENT_TIMEOUT).is_err());
+        let handle = thread::spawn(move || {
+            // The second bind above connected once to check for a running agent
+            for _ in 0..6 {
+                agent.serve_once(None).unwrap();
+            }
+            agent
+        });
+
+        let client = AgentClient {
+            socket_path: socket_path.clone(),
+        };
+        let code = client.code(&store_path, "github", 6, 0).unwrap();
+        assert_eq!(
+            code,
+            get_2fa_code(&Secret::from("GEZDGNBVGY3TQOJQGEZDGNBVGY"), 6, &SystemClock).unwrap()
+        );
+        let code = client.code(&store_path, "steam", 6, 0).unwrap();
+        assert_eq!(
+            code,
+            Otp::steam(&Secret::from("GEZDGNBVGY3TQOJQGEZDGNBVGY"))
+                .unwrap()
+                .totp(&SystemClock)
+                .unwrap()
+        );
+        assert_eq!(
+            client.names(&store_path).unwrap(),
+            vec!["aws", "github", "steam"]
+        );
+        let err = client.code(&store_path, "missing", 6, 30).err().unwrap();
+        assert!(matches!(err, Error::KeyNotFound(names) if names == ["missing"]));
+        let err = client.names(Path::new("test_agent.store")).err().unwrap();
+        assert!(matches!(err, Error::Agent(_)));
+
+        drop(handle.join().unwrap());
+        assert!(!socket_path.exists());
+        fs::remove_file(store_path).unwrap();
+    }
+
+    #[test]
+    fn test_agent_forgets_stores_when_idle() {
+        let socket_path = PathBuf::new().join("test_agent_idle.sock");
+        let store_path = env::current_dir().unwrap().join("test_agent_idle.store");
+        fs::write(&store_path, "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\n").unwrap();
+
+        let mut agent = Agent::bind(&socket_path, Duration::from_millis(50)).unwrap();
+        agent.entries(&store_path).unwrap();
+        assert_eq!(agent.stores.len(), 1);
+
+        agent.serve_once(Some(Duration::from_secs(5))).unwrap();
+        assert!(agent.stores.is_empty());
+
+        drop(agent);
+        fs::remove_file(store_path).unwrap();
+    }
+}
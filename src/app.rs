@@ -1,6 +1,32 @@
-use crate::{store::Store, utils::get_2fa_code};
-use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand};
+#[cfg(unix)]
+use crate::agent::{Agent, AgentClient, DEFAULT_AGENT_TIMEOUT};
+use crate::{
+    audit::AuditLog,
+    backup::{self, Backups, Change, DEFAULT_BACKUP_COUNT},
+    clip::{Clip, ClipboardProvider},
+    config::{Config, CopyMode, OutputFormat, CONFIG_KEYS},
+    error::{Context, Error, Result},
+    format::Entry,
+    lock::{StoreLock, DEFAULT_LOCK_TIMEOUT},
+    menu::{Launcher, Menu},
+    ntp::{self, DEFAULT_NTP_TIMEOUT},
+    otp::{FixedClock, OffsetClock, Otp, OtpType, OtpUri, SystemClock, MAX_WINDOW},
+    permissions,
+    secret::{encode_secret, harden_process, Secret, SecretEncoding},
+    store::Store,
+    utils::{
+        canonical_path, format_timestamp, get_entry_code, glob_match, is_glob, parse_digits,
+        parse_timestamp, DEFAULT_DIGITS,
+    },
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Recovery commands warn once this few unused codes are left
+const LOW_RECOVERY_CODES: usize = 3;
 
 #[derive(Subcommand)]
 pub enum Command {
@@ -11,9 +37,43 @@ pub enum Command {
         /// Name of the key (e.g. github)
         name: String,
 
+        #[clap(long)]
+        /// Folder the key is in, prefixed to its name (e.g. work/aws)
+        folder: Option<String>,
+
+        #[clap(long)]
+        /// Only get the key if it has this tag
+        tag: Option<String>,
+
         #[clap(long, short = 'c')]
-        /// Copy the key to the clipboard
+        /// Copy the key to the clipboard (false prints it, overriding copy_mode)
         copy: Option<bool>,
+
+        #[clap(long = "type", short = 't', conflicts_with = "copy")]
+        /// Type the code into the focused window instead of copying it
+        type_code: bool,
+
+        #[clap(long, short = 'e', requires = "type_code")]
+        /// Press Enter after typing the code
+        enter: bool,
+
+        #[clap(long, value_parser = parse_timestamp)]
+        /// Get the code for this time instead of now, as seconds since the Unix epoch or RFC 3339 (e.g. 2024-05-01T12:00:00Z)
+        at: Option<u64>,
+    },
+
+    #[clap(about = "Check whether a code is valid for a key")]
+    Verify {
+        #[clap(long, short = 'n')]
+        /// Name of the key (e.g. github)
+        name: String,
+
+        /// Code to check
+        code: String,
+
+        #[clap(long, short = 'w', default_value_t = 1, value_parser = clap::value_parser!(u64).range(0..=MAX_WINDOW))]
+        /// Time steps before and after the current one to accept codes from, at most 10
+        window: u64,
     },
 
     #[clap(alias = "a")]
@@ -24,21 +84,89 @@ pub enum Command {
         name: String,
 
         #[clap(long, short = 'k')]
-        /// Key value (e.g. 1234567890)
-        key: String,
+        /// Key value (e.g. JBSWY3DPEHPK3PXPJBSWY3DPEH) or an otpauth:// URI
+        key: Secret,
+
+        #[clap(long, value_enum, default_value = "base32")]
+        /// Encoding of the key value
+        encoding: SecretEncoding,
+
+        #[clap(long = "type", value_enum)]
+        /// Kind of codes the key generates, taken from the URI or totp when left out
+        otp_type: Option<OtpType>,
+
+        #[clap(long = "tag")]
+        /// Tag the key, may be given multiple times
+        tags: Vec<String>,
     },
 
     #[clap(alias = "r")]
     #[clap(about = "Remove a key")]
     Remove {
-        #[clap(long, short = 'n')]
-        /// Name of the key to remove
-        name: String,
+        #[clap(long, short = 'n', num_args = 1.., required = true)]
+        /// Names of the keys to remove, glob patterns like 'work*' are allowed
+        name: Vec<String>,
+
+        #[clap(long, short = 'y')]
+        /// Remove without asking for confirmation
+        yes: bool,
     },
 
     #[clap(alias = "l")]
     #[clap(about = "List all keys")]
-    List,
+    List {
+        #[clap(long)]
+        /// Only list keys with this tag
+        tag: Option<String>,
+
+        #[clap(long)]
+        /// Only list keys in this folder or its subfolders
+        folder: Option<String>,
+
+        #[clap(long, value_enum)]
+        /// Group the keys by folder or tag
+        group_by: Option<GroupBy>,
+    },
+
+    #[clap(alias = "t")]
+    #[clap(about = "Add or remove tags of a key")]
+    Tag {
+        #[clap(long, short = 'n')]
+        /// Name of the key
+        name: String,
+
+        #[clap(long, short = 'a')]
+        /// Tag to add, may be given multiple times
+        add: Vec<String>,
+
+        #[clap(long, short = 'r')]
+        /// Tag to remove, may be given multiple times
+        remove: Vec<String>,
+
+        #[clap(long, conflicts_with = "add")]
+        /// Remove all tags
+        clear: bool,
+    },
+
+    #[clap(about = "Show or change the note of a key")]
+    Note {
+        #[clap(long, short = 'n')]
+        /// Name of the key
+        name: String,
+
+        /// New note, the current one is printed when it's left out
+        text: Option<String>,
+
+        #[clap(long, conflicts_with = "text")]
+        /// Remove the note
+        clear: bool,
+    },
+
+    #[clap(about = "Manage the recovery codes of a key")]
+    Recovery {
+        #[clap(subcommand)]
+        command: RecoveryCommand,
+    },
 
     #[clap(alias = "u")]
     #[clap(about = "Update a key")]
@@ -49,8 +177,287 @@ pub enum Command {
 
         #[clap(long, short = 'k')]
         /// New key value
+        key: Secret,
+
+        #[clap(long, value_enum, default_value = "base32")]
+        /// Encoding of the key value
+        encoding: SecretEncoding,
+    },
+
+    #[clap(about = "Rename a key")]
+    Rename {
+        /// Current name of the key
+        from: String,
+
+        /// New name of the key
+        to: String,
+
+        #[clap(long, short = 'f')]
+        /// Replace the key called <TO> if it exists
+        force: bool,
+    },
+
+    #[clap(about = "Copy a key under a new name")]
+    Copy {
+        /// Name of the key to copy
+        from: String,
+
+        /// Name of the copy
+        to: String,
+
+        #[clap(long, short = 'f')]
+        /// Replace the key called <TO> if it exists
+        force: bool,
+    },
+
+    #[clap(alias = "m")]
+    #[clap(about = "Pick a key from a launcher menu and copy its code")]
+    Menu {
+        #[clap(long, short = 'l', value_enum)]
+        /// Launcher to pick the key with [default: rofi]
+        launcher: Option<Launcher>,
+
+        #[clap(long)]
+        /// Launcher command to run instead of the default one (required for custom)
+        command: Option<String>,
+
+        #[clap(long = "type", short = 't')]
+        /// Type the code into the focused window instead of copying it
+        type_code: bool,
+
+        #[clap(long, short = 'e', requires = "type_code")]
+        /// Press Enter after typing the code
+        enter: bool,
+    },
+
+    #[clap(about = "List, restore or purge removed keys")]
+    Trash {
+        #[clap(subcommand)]
+        command: TrashCommand,
+    },
+
+    #[clap(about = "Undo the last change to the store")]
+    Undo,
+
+    #[clap(about = "Show the history of changes to the store")]
+    Log {
+        #[clap(long, short = 'n')]
+        /// Only show events about this key
+        name: Option<String>,
+
+        #[clap(long)]
+        /// Only show this many of the latest events
+        limit: Option<usize>,
+    },
+
+    #[clap(about = "List or restore backups of the store")]
+    Backup {
+        #[clap(subcommand)]
+        command: BackupCommand,
+    },
+
+    #[cfg(unix)]
+    #[clap(about = "Keep stores in memory and hand out codes to get and menu")]
+    Agent {
+        #[clap(long)]
+        /// Socket to listen on [default: $XDG_RUNTIME_DIR/ova-agent.sock]
+        socket: Option<PathBuf>,
+
+        #[clap(long)]
+        /// Seconds without requests after which the stores are dropped from memory
+        timeout: Option<u64>,
+    },
+
+    #[clap(about = "Make the store file readable by its owner only")]
+    FixPermissions,
+
+    #[clap(about = "Check the system clock against a time server")]
+    Time {
+        #[clap(subcommand)]
+        command: TimeCommand,
+    },
+
+    #[clap(about = "Read or change the configuration file")]
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+
+    #[clap(about = "Manage named vaults")]
+    Vault {
+        #[clap(subcommand)]
+        command: VaultCommand,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    Folder,
+    Tag,
+}
+
+#[derive(Subcommand)]
+pub enum RecoveryCommand {
+    #[clap(about = "Show the recovery codes of a key")]
+    Show {
+        #[clap(long, short = 'n')]
+        /// Name of the key
+        name: String,
+
+        #[clap(long, short = 'a')]
+        /// Also show codes that were already used
+        all: bool,
+    },
+
+    #[clap(about = "Add recovery codes to a key")]
+    Add {
+        #[clap(long, short = 'n')]
+        /// Name of the key
+        name: String,
+
+        #[clap(required = true)]
+        /// Recovery codes as given by the service
+        codes: Vec<String>,
+    },
+
+    #[clap(about = "Mark a recovery code as used")]
+    Use {
+        #[clap(long, short = 'n')]
+        /// Name of the key
+        name: String,
+
+        /// Code to mark, the next unused one is printed and marked when it's left out
+        code: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TrashCommand {
+    #[clap(about = "List removed keys, most recently removed first")]
+    List,
+
+    #[clap(about = "Bring a removed key back")]
+    Restore {
+        /// Name of the key, the most recently removed one wins
+        name: String,
+    },
+
+    #[clap(about = "Delete removed keys for good")]
+    Purge {
+        /// Names of the keys to delete, all of them when left out
+        names: Vec<String>,
+
+        #[clap(long, short = 'y')]
+        /// Purge without asking for confirmation
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BackupCommand {
+    #[clap(about = "List backups of the store, newest first")]
+    List,
+
+    #[clap(about = "Replace the store with a backup")]
+    Restore {
+        /// Number of the backup as shown by backup list
+        id: usize,
+
+        #[clap(long, short = 'y')]
+        /// Restore without asking for confirmation
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TimeCommand {
+    #[clap(about = "Ask an NTP server how far the system clock is off")]
+    Check {
+        #[clap(long, default_value = "pool.ntp.org")]
+        /// NTP server, with an optional port (e.g. time.example.com:123)
+        server: String,
+
+        #[clap(long)]
+        /// Store the offset as time_offset, in the selected vault when there is one
+        save: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum VaultCommand {
+    #[clap(about = "Create a new vault")]
+    Create {
+        /// Name of the vault (e.g. work)
+        name: String,
+
+        #[clap(long = "path")]
+        /// Store file of the vault, defaults to vaults/<name>.store next to the config file
+        path: Option<PathBuf>,
+    },
+
+    #[clap(about = "List all vaults")]
+    List,
+
+    #[clap(about = "Remove a vault from the config, its store file is kept")]
+    Remove {
+        /// Name of the vault
+        name: String,
+    },
+
+    #[clap(about = "Set the vault used when --vault is not given")]
+    Default {
+        /// Name of the vault
+        name: String,
+    },
+
+    #[clap(about = "Move a key into another vault")]
+    Move {
+        #[clap(long, short = 'n')]
+        /// Name of the key
+        name: String,
+
+        #[clap(long)]
+        /// Vault to move the key to
+        to: String,
+    },
+
+    #[clap(about = "Copy a key into another vault")]
+    Copy {
+        #[clap(long, short = 'n')]
+        /// Name of the key
+        name: String,
+
+        #[clap(long)]
+        /// Vault to copy the key to
+        to: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    #[clap(about = "Print a config value")]
+    Get {
+        /// Config key (e.g. digits)
+        key: String,
+    },
+
+    #[clap(about = "Set a config value")]
+    Set {
+        /// Config key (e.g. digits)
+        key: String,
+
+        /// New value
+        value: String,
+    },
+
+    #[clap(about = "Remove a config value, falling back to the default")]
+    Unset {
+        /// Config key (e.g. digits)
         key: String,
     },
+
+    #[clap(about = "List all config values")]
+    List,
 }
 
 #[derive(Parser)]
@@ -58,6 +465,26 @@ pub enum Command {
 pub struct App {
     #[clap(subcommand)]
     pub command: Command,
+
+    #[clap(long, global = true)]
+    /// Path to the store file, overrides OVA_STORE and config
+    pub store: Option<PathBuf>,
+
+    #[clap(long, global = true, conflicts_with = "store")]
+    /// Name of the vault to use instead of the default one
+    pub vault: Option<String>,
+
+    #[clap(long, global = true, value_parser = parse_digits)]
+    /// Number of digits in generated codes (6-8), overrides config
+    pub digits: Option<usize>,
+
+    #[clap(long, global = true, value_enum)]
+    /// Clipboard provider to use, overrides config
+    pub clipboard: Option<ClipboardProvider>,
+
+    #[clap(long, global = true, value_enum)]
+    /// Output format, overrides config
+    pub format: Option<OutputFormat>,
 }
 
 impl Default for App {
@@ -72,49 +499,1167 @@ impl App {
     }
 
     pub fn run(&self) -> Result<()> {
-        let store = Store::new()?;
+        harden_process();
+
+        let config_path = Config::default_path()?;
+        let config = Config::load(&config_path)?;
+
+        match &self.command {
+            Command::Config { command } => {
+                return self.run_config(command, config, &config_path);
+            }
+            Command::Vault { command } => {
+                return self.run_vault(command, config, &config_path);
+            }
+            Command::Backup { command } => {
+                return self.run_backup(command, &config);
+            }
+            Command::Undo => {
+                return self.undo(&config);
+            }
+            Command::Log { name, limit } => {
+                return self.show_log(&config, name.as_deref(), *limit);
+            }
+            Command::FixPermissions => {
+                return self.fix_permissions(&config);
+            }
+            Command::Time { command } => {
+                return self.run_time(command, config, &config_path);
+            }
+            #[cfg(unix)]
+            Command::Agent { socket, timeout } => {
+                let socket = socket.clone().unwrap_or_else(Agent::default_socket_path);
+                let timeout = timeout
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_AGENT_TIMEOUT);
+                let mut agent = Agent::bind(&socket, timeout)?;
+
+                println!(
+                    "OVA_AGENT_SOCK={}; export OVA_AGENT_SOCK;",
+                    agent.socket_path().to_string_lossy()
+                );
+                return agent.serve();
+            }
+            _ => {}
+        }
+
+        let mutating = matches!(
+            self.command,
+            Command::Add { .. }
+                | Command::Remove { .. }
+                | Command::Update { .. }
+                | Command::Rename { .. }
+                | Command::Copy { .. }
+                | Command::Tag { .. }
+                | Command::Note { text: Some(_), .. }
+                | Command::Note { clear: true, .. }
+                | Command::Recovery {
+                    command: RecoveryCommand::Add { .. } | RecoveryCommand::Use { .. }
+                }
+                | Command::Trash {
+                    command: TrashCommand::Restore { .. } | TrashCommand::Purge { .. }
+                }
+        );
+        let vault_digits = match self.current_vault(&config) {
+            Some(vault) => config.vault(vault)?.digits,
+            None => None,
+        };
+        let digits = self
+            .digits
+            .or(vault_digits)
+            .or(config.digits)
+            .unwrap_or(DEFAULT_DIGITS);
+        let format = self.format.or(config.format).unwrap_or(OutputFormat::Text);
+        let clock = OffsetClock {
+            clock: SystemClock,
+            offset: self.time_offset(&config)?,
+        };
+
+        #[cfg(unix)]
+        if let Some(agent) = AgentClient::from_env() {
+            if self.run_with_agent(&agent, &config, digits, clock.offset, format)? {
+                return Ok(());
+            }
+        }
+
+        let (store, _lock) = self.open_store(&config, &self.store_path(&config)?, mutating)?;
+        let log = AuditLog::for_store(store.file_path());
+
         match &self.command {
-            Command::Get { name, copy } => {
-                let key = match store.keys.get(name) {
-                    Some(key) => key,
+            Command::Get {
+                name,
+                folder,
+                tag,
+                copy,
+                type_code,
+                enter,
+                at,
+            } => {
+                let name = &qualified_name(folder.as_deref(), name);
+
+                let entry = match store.entry(name)? {
+                    Some(entry) if tag.as_ref().is_none_or(|tag| entry.tags().contains(tag)) => {
+                        entry
+                    }
+                    _ => {
+                        return Err(Error::KeyNotFound(vec![name.to_string()]));
+                    }
+                };
+
+                let code = match at {
+                    Some(time) => get_entry_code(&entry, digits, &FixedClock(*time))?,
+                    None => get_entry_code(&entry, digits, &clock)?,
+                };
+                if config.log_codes == Some(true) {
+                    audit(&log, "get", name, "");
+                }
+
+                let mode = copy_mode(&config, *copy, *type_code);
+                self.deliver_code(&config, name, &code, mode, *enter, format)?;
+            }
+
+            Command::Verify { name, code, window } => {
+                let entry = store
+                    .entry(name)?
+                    .ok_or_else(|| Error::KeyNotFound(vec![name.to_string()]))?;
+                let otp_type = entry.otp_type()?;
+                let digits = entry.digits()?.unwrap_or(digits);
+
+                let code: String = code
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .map(|c| c.to_ascii_uppercase())
+                    .collect();
+                if !otp_type.is_well_formed(&code, digits) {
+                    return Err(Error::Invalid(match otp_type {
+                        OtpType::Totp => format!("Code must be {} digits, see --digits", digits),
+                        OtpType::Steam => "Steam Guard codes are 5 letters and digits".to_string(),
+                    }));
+                }
+
+                let offset = Otp::for_type(&entry.value, otp_type, digits)?
+                    .verify(&code, &clock, *window)?
+                    .ok_or_else(|| Error::CodeMismatch {
+                        name: name.to_string(),
+                        window: *window,
+                    })?;
+
+                match format {
+                    OutputFormat::Text => {
+                        println!("Code matches {} at time step offset {:+}", name, offset)
+                    }
+                    OutputFormat::Plain => println!("{}", offset),
+                }
+            }
+
+            Command::Add {
+                name,
+                key,
+                encoding,
+                otp_type,
+                tags,
+            } => {
+                let (key, otp_type, key_digits) = if OtpUri::is_uri(key.expose()) {
+                    let uri = OtpUri::parse(key.expose())?;
+                    (uri.secret, otp_type.unwrap_or(uri.otp_type), uri.digits)
+                } else {
+                    (
+                        encode_secret(key.expose(), *encoding)?,
+                        otp_type.unwrap_or_default(),
+                        None,
+                    )
+                };
+
+                store.add_entry(name, key.expose(), otp_type, key_digits, tags)?;
+                audit(&log, "add", name, "");
+                println!("Added {}", name);
+            }
+            Command::Remove { name: names, yes } => {
+                let mut removed: Vec<&str> = vec![];
+                let mut missing: Vec<String> = vec![];
+
+                for name in names {
+                    let mut matched: Vec<&str> = if is_glob(name) {
+                        store
+                            .keys
+                            .keys()
+                            .filter(|key| glob_match(name, key))
+                            .map(|key| key.as_str())
+                            .collect()
+                    } else {
+                        store
+                            .keys
+                            .get_key_value(name)
+                            .map(|(key, _)| key.as_str())
+                            .into_iter()
+                            .collect()
+                    };
+
+                    if matched.is_empty() {
+                        missing.push(name.clone());
+                    }
+
+                    matched.sort();
+                    for key in matched {
+                        if !removed.contains(&key) {
+                            removed.push(key);
+                        }
+                    }
+                }
+
+                if !removed.is_empty() {
+                    let question = format!("Move {} to the trash?", removed.join(", "));
+                    if !yes && !confirm(&question)? {
+                        return Err(Error::Invalid("Remove cancelled".to_string()));
+                    }
+
+                    store.trash_many_in_store(&removed)?;
+                    for name in &removed {
+                        audit(&log, "remove", name, "");
+                    }
+                    println!("Moved {} to the trash", removed.join(", "));
+                }
+
+                if !missing.is_empty() {
+                    return Err(Error::KeyNotFound(missing));
+                }
+            }
+            Command::List {
+                tag,
+                folder,
+                group_by,
+            } => {
+                let entries: Vec<Entry> = store
+                    .entries()?
+                    .into_iter()
+                    .filter(|entry| tag.as_ref().is_none_or(|tag| entry.tags().contains(tag)))
+                    .filter(|entry| folder.as_ref().is_none_or(|folder| entry.in_folder(folder)))
+                    .collect();
+
+                print_entries(&entries, format, *group_by);
+            }
+
+            Command::Tag {
+                name,
+                add,
+                remove,
+                clear,
+            } => {
+                let entry = store
+                    .entry(name)?
+                    .ok_or_else(|| Error::KeyNotFound(vec![name.to_string()]))?;
+
+                let mut tags = if *clear { vec![] } else { entry.tags() };
+                tags.retain(|tag| !remove.contains(tag));
+                for tag in add {
+                    if !tags.contains(tag) {
+                        tags.push(tag.clone());
+                    }
+                }
+
+                store.set_tags(name, &tags)?;
+                audit(&log, "tag", name, &tags.join(", "));
+                println!("Tags of {}: {}", name, tags.join(", "));
+            }
+
+            Command::Note { name, text, clear } => {
+                if text.is_some() || *clear {
+                    store.set_note(name, text.as_deref())?;
+                    audit(&log, "note", name, "");
+                    match text {
+                        Some(_) => println!("Updated note of {}", name),
+                        None => println!("Removed note of {}", name),
+                    }
+                } else {
+                    let entry = store
+                        .entry(name)?
+                        .ok_or_else(|| Error::KeyNotFound(vec![name.to_string()]))?;
+                    match entry.note() {
+                        Some(note) => println!("{}", note),
+                        None => return Err(Error::Invalid(format!("{} has no note", name))),
+                    }
+                }
+            }
+
+            Command::Recovery { command } => {
+                run_recovery(command, &store, &log, format)?;
+            }
+
+            Command::Trash { command } => match command {
+                TrashCommand::List => {
+                    for entry in store.trash()? {
+                        match format {
+                            OutputFormat::Text => println!(
+                                "{}\tremoved {}",
+                                entry.name,
+                                format_timestamp(entry.deleted().unwrap_or_default())
+                            ),
+                            OutputFormat::Plain => println!("{}", entry.name),
+                        }
+                    }
+                }
+                TrashCommand::Restore { name } => {
+                    store.restore_from_trash(name)?;
+                    audit(&log, "restore", name, "");
+                    println!("Restored {}", name);
+                }
+                TrashCommand::Purge { names, yes } => {
+                    let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+                    if names.is_empty() && !yes && !confirm("Delete every key in the trash?")? {
+                        return Err(Error::Invalid("Purge cancelled".to_string()));
+                    }
+
+                    let purged = store.purge_trash(&names)?;
+                    for name in &purged {
+                        audit(&log, "purge", name, "");
+                    }
+                    println!("Deleted {} keys from the trash", purged.len());
+                }
+            },
+
+            Command::Update {
+                name,
+                key,
+                encoding,
+            } => {
+                let key = encode_secret(key.expose(), *encoding)?;
+                store.update_store(name, key.expose())?;
+                audit(&log, "update", name, "");
+                println!("Updated {}", name);
+            }
+
+            Command::Rename { from, to, force } => {
+                store.rename_in_store(from, to, *force)?;
+                audit(&log, "rename", from, to);
+                println!("Renamed {} to {}", from, to);
+            }
+
+            Command::Copy { from, to, force } => {
+                store.copy_in_store(from, to, *force)?;
+                audit(&log, "copy", from, to);
+                println!("Copied {} to {}", from, to);
+            }
+
+            Command::Menu {
+                launcher,
+                command,
+                type_code,
+                enter,
+            } => {
+                let mut names: Vec<&String> = store.keys.keys().collect();
+                names.sort();
+
+                let name = self.pick(&config, *launcher, command.as_deref(), &names)?;
+                let entry = match store.entry(&name)? {
+                    Some(entry) => entry,
                     None => {
-                        return Err(anyhow!("Key not found"));
+                        return Err(Error::KeyNotFound(vec![name.to_string()]));
                     }
                 };
 
-                let code = get_2fa_code(key)?;
+                let code = get_entry_code(&entry, digits, &clock)?;
+                if config.log_codes == Some(true) {
+                    audit(&log, "get", &name, "");
+                }
 
-                if copy.is_some() {
-                    let mut clip = crate::clip::Clip::new()?;
-                    clip.copy(&code)?;
-                    println!("Copied code for {} to clipboard", name);
+                let mode = menu_mode(&config, *type_code);
+                self.deliver_code(&config, &name, &code, mode, *enter, OutputFormat::Plain)?;
+            }
+
+            Command::Config { .. }
+            | Command::Vault { .. }
+            | Command::Backup { .. }
+            | Command::Undo
+            | Command::Log { .. }
+            | Command::FixPermissions
+            | Command::Time { .. } => {
+                unreachable!("these commands are handled before the store is opened")
+            }
+            #[cfg(unix)]
+            Command::Agent { .. } => {
+                unreachable!("the agent is started before the store is opened")
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_config(&self, command: &ConfigCommand, mut config: Config, path: &Path) -> Result<()> {
+        match command {
+            ConfigCommand::Get { key } => match config.get(key)? {
+                Some(value) => println!("{}", value),
+                None => return Err(Error::Config(format!("{} is not set", key))),
+            },
+            ConfigCommand::Set { key, value } => {
+                config.set(key, value)?;
+                config.save(path)?;
+                println!("Set {} to {}", key, value);
+            }
+            ConfigCommand::Unset { key } => {
+                config.unset(key)?;
+                config.save(path)?;
+                println!("Unset {}", key);
+            }
+            ConfigCommand::List => {
+                for key in CONFIG_KEYS {
+                    if let Some(value) = config.get(key)? {
+                        println!("{} = {}", key, value);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_vault(&self, command: &VaultCommand, mut config: Config, path: &Path) -> Result<()> {
+        match command {
+            VaultCommand::Create { name, path: store } => {
+                let store = match store {
+                    Some(store) => store.clone(),
+                    None => path
+                        .parent()
+                        .unwrap_or(Path::new(""))
+                        .join("vaults")
+                        .join(format!("{}.store", name)),
+                };
+
+                config.add_vault(name, store.clone())?;
+                Store::open(&store)?;
+                config.save(path)?;
+                println!("Created vault {} at {}", name, store.to_string_lossy());
+            }
+            VaultCommand::List => {
+                for (name, vault) in config.vaults.iter() {
+                    let marker = if config.default_vault.as_ref() == Some(name) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!("{} {}\t{}", marker, name, vault.store.to_string_lossy());
+                }
+            }
+            VaultCommand::Remove { name } => {
+                let vault = config.remove_vault(name)?;
+                config.save(path)?;
+                println!(
+                    "Removed vault {}, its store is kept at {}",
+                    name,
+                    vault.store.to_string_lossy()
+                );
+            }
+            VaultCommand::Default { name } => {
+                config.set("default_vault", name)?;
+                config.save(path)?;
+                println!("Default vault is now {}", name);
+            }
+            VaultCommand::Move { name, to } | VaultCommand::Copy { name, to } => {
+                let source_path = self.store_path(&config)?;
+                let target_path = config.vault(to)?.store.clone();
+
+                let source_order = lock_order(&source_path);
+                let target_order = lock_order(&target_path);
+                if source_order == target_order {
+                    return Err(Error::Invalid(format!(
+                        "Vault {} uses the same store as the source",
+                        to
+                    )));
+                }
+
+                // Both stores are locked in the same order whichever way the
+                // key goes, so moves in opposite directions don't wait on
+                // each other's lock
+                let ((source, _source_lock), (target, _target_lock)) =
+                    if source_order < target_order {
+                        let source = self.open_store(&config, &source_path, true)?;
+                        (source, self.open_store(&config, &target_path, true)?)
+                    } else {
+                        let target = self.open_store(&config, &target_path, true)?;
+                        (self.open_store(&config, &source_path, true)?, target)
+                    };
+
+                // All attributes go along, the code type and digits as well as
+                // notes and recovery codes
+                let source_log = AuditLog::for_store(&source_path);
+                source.copy_entry_to(name, &target)?;
+                audit(&AuditLog::for_store(&target_path), "add", name, "");
+
+                if let VaultCommand::Move { .. } = command {
+                    source.trash_many_in_store(&[name])?;
+                    audit(&source_log, "vault-move", name, to);
+                    println!("Moved {} to vault {}", name, to);
                 } else {
-                    println!("Key: {}", code);
+                    audit(&source_log, "vault-copy", name, to);
+                    println!("Copied {} to vault {}", name, to);
                 }
             }
+        }
+
+        Ok(())
+    }
+
+    /// Answers get and menu through the agent, returning false when the
+    /// command needs the store itself or the agent isn't running
+    #[cfg(unix)]
+    fn run_with_agent(
+        &self,
+        agent: &AgentClient,
+        config: &Config,
+        digits: usize,
+        time_offset: i64,
+        format: OutputFormat,
+    ) -> Result<bool> {
+        if !matches!(
+            self.command,
+            Command::Get {
+                tag: None,
+                at: None,
+                ..
+            } | Command::Menu { .. }
+        ) {
+            return Ok(false);
+        }
 
-            Command::Add { name, key } => {
-                store.insert_into_store(name, key)?;
-                println!("Added {} with key {}", name, key);
+        if !agent.ping() {
+            eprintln!("Warning: the agent is not running, reading the store directly");
+            return Ok(false);
+        }
+
+        // The agent runs in a directory of its own, so it needs a path that
+        // doesn't depend on ours
+        let path = canonical_path(&self.store_path(config)?);
+        let log = AuditLog::for_store(&path);
+
+        let (name, mode, enter, format) = match &self.command {
+            Command::Get {
+                name,
+                folder,
+                copy,
+                type_code,
+                enter,
+                ..
+            } => (
+                qualified_name(folder.as_deref(), name),
+                copy_mode(config, *copy, *type_code),
+                *enter,
+                format,
+            ),
+            Command::Menu {
+                launcher,
+                command,
+                type_code,
+                enter,
+            } => {
+                let names = agent.names(&path)?;
+                let names: Vec<&String> = names.iter().collect();
+                (
+                    self.pick(config, *launcher, command.as_deref(), &names)?,
+                    menu_mode(config, *type_code),
+                    *enter,
+                    OutputFormat::Plain,
+                )
             }
-            Command::Remove { name } => {
-                store.delete_from_store(name)?;
-                println!("Removed {}", name);
+            _ => return Ok(false),
+        };
+
+        let code = agent.code(&path, &name, digits, time_offset)?;
+        if config.log_codes == Some(true) {
+            audit(&log, "get", &name, "");
+        }
+
+        self.deliver_code(config, &name, &code, mode, enter, format)?;
+        Ok(true)
+    }
+
+    /// Lets the user pick one of `names` from the launcher menu
+    fn pick(
+        &self,
+        config: &Config,
+        launcher: Option<Launcher>,
+        command: Option<&str>,
+        names: &[&String],
+    ) -> Result<String> {
+        let launcher = launcher.or(config.launcher).unwrap_or(Launcher::Rofi);
+        let command = command.or(config.launcher_command.as_deref());
+
+        Menu::new(launcher, command)?.select(names)
+    }
+
+    fn deliver_code(
+        &self,
+        config: &Config,
+        name: &str,
+        code: &str,
+        mode: CopyMode,
+        enter: bool,
+        format: OutputFormat,
+    ) -> Result<()> {
+        match mode {
+            CopyMode::Type => {
+                self.clip(config)?.type_text(code, enter)?;
             }
-            Command::List => {
-                println!("Key\tValue");
-                println!("-----\t-----");
-                for (name, key) in store.keys.iter() {
-                    println!("{}\t{}", name, key);
+            CopyMode::Copy => {
+                self.clip(config)?.copy(code)?;
+                if format == OutputFormat::Text {
+                    println!("Copied code for {} to clipboard", name);
                 }
             }
+            CopyMode::Print => match format {
+                OutputFormat::Text => println!("Key: {}", code),
+                OutputFormat::Plain => println!("{}", code),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Seconds added to the system time for codes, the vault's offset wins
+    /// over the global one
+    fn time_offset(&self, config: &Config) -> Result<i64> {
+        let vault_offset = match self.current_vault(config) {
+            Some(vault) => config.vault(vault)?.time_offset,
+            None => None,
+        };
+
+        Ok(vault_offset.or(config.time_offset).unwrap_or(0))
+    }
+
+    /// Vault selected with --vault, or the default one unless a store path was given
+    fn current_vault<'a>(&'a self, config: &'a Config) -> Option<&'a str> {
+        if self.vault.is_some() {
+            return self.vault.as_deref();
+        }
+
+        if self.store.is_some() || Store::env_path().is_some() {
+            return None;
+        }
+
+        config.default_vault.as_deref()
+    }
+
+    fn store_path(&self, config: &Config) -> Result<PathBuf> {
+        if let Some(path) = &self.store {
+            return Ok(path.clone());
+        }
+
+        if let Some(vault) = self.current_vault(config) {
+            return Ok(config.vault(vault)?.store.clone());
+        }
 
-            Command::Update { name, key } => {
-                store.update_store(name, key)?;
-                println!("Updated {} with key {}", name, key);
+        match &config.store {
+            Some(path) if Store::env_path().is_none() => Ok(path.clone()),
+            _ => Store::default_path(),
+        }
+    }
+
+    /// Opens the store at `path`, locking it first when the command is going to
+    /// change it so the lock covers the whole load-modify-save cycle
+    fn open_store(
+        &self,
+        config: &Config,
+        path: &Path,
+        mutating: bool,
+    ) -> Result<(Store, Option<StoreLock>)> {
+        let lock = if mutating {
+            Some(self.lock_store(config, path)?)
+        } else {
+            None
+        };
+
+        let mut store = Store::open(path)?;
+        if mutating {
+            store = store.with_backups(self.backups(config, path));
+        }
+        for issue in permissions::check(path)? {
+            eprintln!("Warning: {}, run `ova fix-permissions` to fix it", issue);
+        }
+
+        Ok((store, lock))
+    }
+
+    fn lock_store(&self, config: &Config, path: &Path) -> Result<StoreLock> {
+        let timeout = config
+            .lock_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_LOCK_TIMEOUT);
+
+        StoreLock::acquire(path, timeout)
+    }
+
+    fn backups(&self, config: &Config, path: &Path) -> Backups {
+        Backups::new(
+            path,
+            config.backup_dir.as_deref(),
+            config.backup_count.unwrap_or(DEFAULT_BACKUP_COUNT),
+        )
+    }
+
+    fn run_backup(&self, command: &BackupCommand, config: &Config) -> Result<()> {
+        let path = self.store_path(config)?;
+        let backups = self.backups(config, &path);
+
+        match command {
+            BackupCommand::List => {
+                let list = backups.list()?;
+                if list.is_empty() {
+                    println!("No backups of {}", path.to_string_lossy());
+                }
+
+                for (i, backup) in list.iter().enumerate() {
+                    let entries = backup.read()?.entries().len();
+                    println!(
+                        "{}\t{}\t{} keys",
+                        i + 1,
+                        format_timestamp((backup.created / 1000) as u64),
+                        entries
+                    );
+                }
+            }
+            BackupCommand::Restore { id, yes } => {
+                let _lock = self.lock_store(config, &path)?;
+                let backup = backups.get(*id)?;
+                let (store, _) = self.open_store(config, &path, false)?;
+
+                let changes = backup::diff(&store.entries()?, &backup.read()?.entries());
+                if changes.is_empty() {
+                    println!("The store already matches backup {}", id);
+                    return Ok(());
+                }
+
+                print_changes(&changes);
+
+                if !yes && !confirm(&format!("Restore backup {}?", id))? {
+                    return Err(Error::Invalid("Restore cancelled".to_string()));
+                }
+
+                backups.restore(&backup)?;
+                record_changes(&AuditLog::for_store(&path), "backup-restore", &changes);
+                println!(
+                    "Restored backup {} from {}",
+                    id,
+                    format_timestamp((backup.created / 1000) as u64)
+                );
             }
         }
 
         Ok(())
     }
+
+    fn undo(&self, config: &Config) -> Result<()> {
+        let path = self.store_path(config)?;
+        let backups = self.backups(config, &path);
+        let _lock = self.lock_store(config, &path)?;
+        let (store, _) = self.open_store(config, &path, false)?;
+
+        let backup = match backups.last_change()? {
+            Some(backup) => backup,
+            None if config.backup_count == Some(0) => {
+                return Err(Error::Invalid(
+                    "Nothing to undo, backups are turned off".to_string(),
+                ));
+            }
+            None => return Err(Error::Invalid("Nothing to undo".to_string())),
+        };
+
+        let changes = backup::diff(&store.entries()?, &backup.read()?.entries());
+        backups.undo(&backup)?;
+        record_changes(&AuditLog::for_store(&path), "undo", &changes);
+
+        println!(
+            "Undid the change made after {}",
+            format_timestamp((backup.created / 1000) as u64)
+        );
+        print_changes(&changes);
+
+        Ok(())
+    }
+
+    fn show_log(&self, config: &Config, name: Option<&str>, limit: Option<usize>) -> Result<()> {
+        let format = self.format.or(config.format).unwrap_or(OutputFormat::Text);
+        let mut events = AuditLog::for_store(&self.store_path(config)?).events()?;
+        if let Some(name) = name {
+            events.retain(|event| event.involves(name));
+        }
+        if let Some(limit) = limit {
+            events.drain(..events.len().saturating_sub(limit));
+        }
+
+        for event in events {
+            let time = match format {
+                OutputFormat::Text => format_timestamp(event.time),
+                OutputFormat::Plain => event.time.to_string(),
+            };
+            println!(
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                time, event.host, event.user, event.action, event.name, event.detail
+            );
+        }
+
+        Ok(())
+    }
+
+    fn fix_permissions(&self, config: &Config) -> Result<()> {
+        let path = self.store_path(config)?;
+        let _lock = self.lock_store(config, &path)?;
+
+        let fixed = permissions::fix(&path)?;
+        if fixed.is_empty() {
+            println!("Permissions of {} are fine", path.to_string_lossy());
+        }
+        for issue in fixed {
+            println!(
+                "Changed mode of {} from {:o} to {:o}",
+                issue.path.to_string_lossy(),
+                issue.mode,
+                issue.fixed_mode
+            );
+        }
+
+        Ok(())
+    }
+
+    fn run_time(&self, command: &TimeCommand, mut config: Config, path: &Path) -> Result<()> {
+        let TimeCommand::Check { server, save } = command;
+        let format = self.format.or(config.format).unwrap_or(OutputFormat::Text);
+
+        let offset = ntp::query(server, DEFAULT_NTP_TIMEOUT)?;
+        let seconds = offset.seconds();
+
+        match format {
+            OutputFormat::Text => {
+                let state = if seconds == 0 {
+                    "in sync with".to_string()
+                } else if seconds > 0 {
+                    format!("{:.3} seconds behind", offset.offset)
+                } else {
+                    format!("{:.3} seconds ahead of", -offset.offset)
+                };
+                println!(
+                    "System clock is {} {} (round trip {:.0} ms)",
+                    state,
+                    server,
+                    offset.delay * 1000.0
+                );
+            }
+            OutputFormat::Plain => println!("{:.3}", offset.offset),
+        }
+
+        let current = self.time_offset(&config)?;
+        if *save {
+            match self.current_vault(&config).map(|vault| vault.to_string()) {
+                Some(vault) => {
+                    config
+                        .vaults
+                        .get_mut(&vault)
+                        .ok_or_else(|| Error::Config(format!("Vault {} does not exist", vault)))?
+                        .time_offset = Some(seconds);
+                    config.save(path)?;
+                    println!(
+                        "Saved time offset of {} seconds for vault {}",
+                        seconds, vault
+                    );
+                }
+                None => {
+                    config.time_offset = Some(seconds);
+                    config.save(path)?;
+                    println!("Saved time offset of {} seconds", seconds);
+                }
+            }
+        } else if seconds != current {
+            eprintln!(
+                "Codes are generated with a time offset of {} seconds, run `ova time check --save` to use {}",
+                current, seconds
+            );
+        }
+
+        Ok(())
+    }
+
+    fn clip(&self, config: &Config) -> Result<Clip> {
+        let provider = self
+            .clipboard
+            .or(config.clipboard)
+            .unwrap_or(ClipboardProvider::Auto);
+
+        Clip::with_provider(provider)
+    }
+}
+
+/// Name of a key inside `folder`, e.g. `work/aws` for `--folder work -n aws`
+fn qualified_name(folder: Option<&str>, name: &str) -> String {
+    match folder {
+        Some(folder) => format!("{}/{}", folder.trim_end_matches('/'), name),
+        None => name.to_string(),
+    }
+}
+
+/// Where the store at `path` sorts when several stores are locked at once
+fn lock_order(path: &Path) -> PathBuf {
+    canonical_path(path)
+}
+
+/// What `get` does with a code, the flags win over the config
+fn copy_mode(config: &Config, copy: Option<bool>, type_code: bool) -> CopyMode {
+    match (type_code, copy) {
+        (true, _) => CopyMode::Type,
+        (false, Some(true)) => CopyMode::Copy,
+        (false, Some(false)) => CopyMode::Print,
+        (false, None) => config.copy_mode.unwrap_or(CopyMode::Print),
+    }
+}
+
+/// The menu has no terminal to print to, so it copies unless typing is asked
+/// for, and its codes are delivered with the quiet plain format
+fn menu_mode(config: &Config, type_code: bool) -> CopyMode {
+    if type_code || config.copy_mode == Some(CopyMode::Type) {
+        CopyMode::Type
+    } else {
+        CopyMode::Copy
+    }
+}
+
+/// Appends to the audit log. The store has already been changed by then, so
+/// failing the command would make a retry run into the change, e.g. a key
+/// that already exists. A failed write is only a warning.
+fn audit(log: &AuditLog, action: &str, name: &str, detail: &str) {
+    if let Err(err) = log.record(action, name, detail) {
+        match std::error::Error::source(&err) {
+            Some(source) => eprintln!("Warning: {}: {}, the change itself was made", err, source),
+            None => eprintln!("Warning: {}, the change itself was made", err),
+        }
+    }
+}
+
+/// One event per entry a restore changed, the detail says how
+fn record_changes(log: &AuditLog, action: &str, changes: &[Change]) {
+    for change in changes {
+        match change {
+            Change::Added(name) => audit(log, action, name, "added"),
+            Change::Removed(name) => audit(log, action, name, "removed"),
+            Change::Changed(name) => audit(log, action, name, "changed"),
+        }
+    }
+}
+
+fn print_changes(changes: &[Change]) {
+    for change in changes {
+        match change {
+            Change::Added(name) => println!("+ {}", name),
+            Change::Removed(name) => println!("- {}", name),
+            Change::Changed(name) => println!("~ {}", name),
+        }
+    }
+}
+
+/// Asks a yes or no question on the terminal, refusing to guess when there's
+/// nobody to answer
+fn confirm(question: &str) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Err(Error::Invalid(
+            "Not asking for confirmation without a terminal, pass --yes".to_string(),
+        ));
+    }
+
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    io::stderr()
+        .flush()
+        .and_then(|_| io::stdin().read_line(&mut answer))
+        .with_context(|| "Error reading the answer")?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes" | "Yes"))
+}
+
+fn run_recovery(
+    command: &RecoveryCommand,
+    store: &Store,
+    log: &AuditLog,
+    format: OutputFormat,
+) -> Result<()> {
+    let name = match command {
+        RecoveryCommand::Show { name, .. }
+        | RecoveryCommand::Add { name, .. }
+        | RecoveryCommand::Use { name, .. } => name,
+    };
+    let entry = store
+        .entry(name)?
+        .ok_or_else(|| Error::KeyNotFound(vec![name.to_string()]))?;
+    let mut unused: Vec<String> = entry
+        .recovery_codes()
+        .iter()
+        .map(|code| code.to_string())
+        .collect();
+    let mut used: Vec<String> = entry
+        .used_recovery_codes()
+        .iter()
+        .map(|code| code.to_string())
+        .collect();
+
+    match command {
+        RecoveryCommand::Show { all, .. } => {
+            if unused.is_empty() && (used.is_empty() || !all) {
+                return Err(Error::Invalid(format!(
+                    "{} has no unused recovery codes",
+                    name
+                )));
+            }
+
+            for code in &unused {
+                println!("{}", code);
+            }
+            if *all && format == OutputFormat::Text {
+                for code in &used {
+                    println!("{} (used)", code);
+                }
+            }
+        }
+        RecoveryCommand::Add { codes, .. } => {
+            let mut added = 0;
+            for code in codes {
+                let code = code.trim().to_string();
+                if !unused
+                    .iter()
+                    .chain(&used)
+                    .any(|known| same_code(known, &code))
+                {
+                    unused.push(code);
+                    added += 1;
+                }
+            }
+
+            store.set_recovery_codes(name, &unused, &used)?;
+            audit(log, "recovery-add", name, "");
+            println!("Added {} recovery codes to {}", added, name);
+        }
+        RecoveryCommand::Use { code, .. } => {
+            let index = match code {
+                Some(code) => match unused.iter().position(|known| same_code(known, code)) {
+                    Some(index) => index,
+                    None if used.iter().any(|known| same_code(known, code)) => {
+                        return Err(Error::Invalid(format!(
+                            "Recovery code {} was already used",
+                            code
+                        )));
+                    }
+                    None => {
+                        return Err(Error::Invalid(format!(
+                            "{} has no recovery code {}",
+                            name, code
+                        )))
+                    }
+                },
+                None if unused.is_empty() => {
+                    return Err(Error::Invalid(format!(
+                        "{} has no unused recovery codes",
+                        name
+                    )));
+                }
+                None => 0,
+            };
+
+            let code = unused.remove(index);
+            used.push(code.clone());
+            store.set_recovery_codes(name, &unused, &used)?;
+            audit(log, "recovery-use", name, "");
+
+            match format {
+                OutputFormat::Text => println!("Used recovery code {} of {}", code, name),
+                OutputFormat::Plain => println!("{}", code),
+            }
+        }
+    }
+
+    match unused.len() {
+        0 => eprintln!("Warning: {} has no recovery codes left", name),
+        left if left <= LOW_RECOVERY_CODES => {
+            eprintln!("Warning: {} has only {} recovery codes left", name, left)
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Services print recovery codes in groups, `abcd-efgh` and `ABCD EFGH` are
+/// the same code
+fn same_code(a: &str, b: &str) -> bool {
+    let normalize = |code: &str| -> String {
+        code.chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+
+    normalize(a) == normalize(b)
+}
+
+fn print_entries(entries: &[Entry], format: OutputFormat, group_by: Option<GroupBy>) {
+    let with_tags = entries.iter().any(|entry| !entry.tags().is_empty());
+    let row = |entry: &Entry, name: &str| {
+        if with_tags && format == OutputFormat::Text {
+            format!(
+                "{}\t{}\t{}",
+                name,
+                entry.value.expose(),
+                entry.tags().join(", ")
+            )
+        } else {
+            format!("{}\t{}", name, entry.value.expose())
+        }
+    };
+
+    if format == OutputFormat::Text {
+        if with_tags {
+            println!("Key\tValue\tTags");
+            println!("-----\t-----\t-----");
+        } else {
+            println!("Key\tValue");
+            println!("-----\t-----");
+        }
+    }
+
+    let group_by = match group_by {
+        Some(group_by) => group_by,
+        None => {
+            for entry in entries {
+                println!("{}", row(entry, &entry.name));
+            }
+            return;
+        }
+    };
+
+    let mut groups: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        let keys = match group_by {
+            GroupBy::Folder => vec![entry.folder().unwrap_or_default().to_string()],
+            GroupBy::Tag if entry.tags().is_empty() => vec![String::new()],
+            GroupBy::Tag => entry.tags(),
+        };
+
+        for key in keys {
+            groups.entry(key).or_default().push(entry);
+        }
+    }
+
+    for (group, entries) in groups {
+        if format == OutputFormat::Text {
+            let title = match (group.is_empty(), group_by) {
+                (true, GroupBy::Folder) => "(no folder)",
+                (true, GroupBy::Tag) => "(untagged)",
+                (false, _) => group.as_str(),
+            };
+            println!("\n{}", title);
+        }
+
+        for entry in entries {
+            let name = match group_by {
+                GroupBy::Folder => entry.name.rsplit('/').next().unwrap_or_default(),
+                GroupBy::Tag => entry.name.as_str(),
+            };
+
+            match format {
+                OutputFormat::Text => println!("  {}", row(entry, name)),
+                OutputFormat::Plain => println!("{}\t{}", group, row(entry, &entry.name)),
+            }
+        }
+    }
 }
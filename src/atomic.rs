@@ -0,0 +1,181 @@
+use crate::error::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Replaces `path` with `contents` without ever leaving a half written file behind
+pub fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    write_file_with(path, |file| file.write_all(contents))
+}
+
+/// Lets `write` fill a temp file in the same directory as `path`, syncs it to
+/// disk and renames it over `path`. The original file, including its
+/// permissions, is left untouched if anything fails on the way.
+pub fn write_file_with<F>(path: &Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut File) -> std::io::Result<()>,
+{
+    let temp_path = temp_path(path);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    // New files are private, existing ones get their permissions copied below
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(crate::permissions::PRIVATE_FILE_MODE);
+    }
+
+    let mut file = options.open(&temp_path).with_context(|| {
+        format!(
+            "Error creating temporary file at - {}",
+            temp_path.to_string_lossy()
+        )
+    })?;
+
+    let result = (|| -> std::io::Result<()> {
+        write(&mut file)?;
+        file.flush()?;
+
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.with_context(|| format!("Error writing file at - {}", path.to_string_lossy()))?;
+
+    sync_dir(path);
+    Ok(())
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{}.{}-{}.tmp", file_name, process::id(), nanos))
+}
+
+/// Makes the rename itself durable, best effort since not every platform can
+/// open a directory
+fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::remove_file;
+    use std::io::{Error, ErrorKind};
+
+    fn temp_files(name: &str) -> Vec<String> {
+        fs::read_dir(".")
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|file| file.starts_with(&format!(".{}.", name)) && file.ends_with(".tmp"))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_file() {
+        let path = PathBuf::new().join("test_atomic_write.store");
+        fs::write(&path, "old").unwrap();
+
+        write_file(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(temp_files("test_atomic_write.store").is_empty());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_file_creates_missing_file() {
+        let path = PathBuf::new().join("test_atomic_missing.store");
+
+        write_file(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_keeps_original() {
+        let path = PathBuf::new().join("test_atomic_failed.store");
+        fs::write(&path, "github = JBSWY3DPEHPK3PXP\n").unwrap();
+
+        let result = write_file_with(&path, |file| {
+            file.write_all(b"git")?;
+            Err(Error::new(ErrorKind::StorageFull, "disk full"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "github = JBSWY3DPEHPK3PXP\n"
+        );
+        assert!(temp_files("test_atomic_failed.store").is_empty());
+
+        remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_preserves_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = PathBuf::new().join("test_atomic_permissions.store");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_file(&path, b"new").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_file_creates_private_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = PathBuf::new().join("test_atomic_private.store");
+
+        write_file(&path, b"new").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        remove_file(path).unwrap();
+    }
+}
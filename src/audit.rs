@@ -0,0 +1,198 @@
+use crate::error::{Context, Result};
+use crate::permissions;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One line of the audit log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub host: String,
+    pub user: String,
+    pub action: String,
+    pub name: String,
+    /// Second key or vault involved, e.g. the new name of a rename
+    pub detail: String,
+}
+
+impl Event {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let event = Event {
+            time: fields.next()?.parse().ok()?,
+            host: fields.next()?.to_string(),
+            user: fields.next()?.to_string(),
+            action: fields.next()?.to_string(),
+            name: fields.next()?.to_string(),
+            detail: fields.next().unwrap_or_default().to_string(),
+        };
+
+        Some(event)
+    }
+
+    fn format(&self) -> String {
+        [
+            self.time.to_string().as_str(),
+            &self.host,
+            &self.user,
+            &self.action,
+            &self.name,
+            &self.detail,
+        ]
+        .iter()
+        .map(|field| field.replace(|c: char| c.is_control(), " "))
+        .collect::<Vec<String>>()
+        .join("\t")
+    }
+
+    /// Whether the event is about the key `name`
+    pub fn involves(&self, name: &str) -> bool {
+        self.name == name || self.detail == name
+    }
+}
+
+/// Append-only log of what happened to a store, kept next to it as
+/// `<store file>.log` with one tab separated event per line
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    pub fn for_store(store_path: &Path) -> Self {
+        let mut file_name = store_path
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_default();
+        file_name.push(".log");
+
+        AuditLog {
+            path: store_path.with_file_name(file_name),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, action: &str, name: &str, detail: &str) -> Result<()> {
+        let event = Event {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or_default(),
+            host: hostname(),
+            user: username(),
+            action: action.to_string(),
+            name: name.to_string(),
+            detail: detail.to_string(),
+        };
+
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            permissions::create_private_dir(dir)?;
+        }
+
+        self.append(&event).with_context(|| {
+            format!(
+                "Error writing audit log at - {}",
+                self.path.to_string_lossy()
+            )
+        })
+    }
+
+    /// All events, oldest first. Lines that don't parse are skipped.
+    pub fn events(&self) -> Result<Vec<Event>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        let content = fs::read_to_string(&self.path).with_context(|| {
+            format!(
+                "Error reading audit log at - {}",
+                self.path.to_string_lossy()
+            )
+        })?;
+
+        Ok(content.lines().filter_map(Event::parse).collect())
+    }
+
+    fn append(&self, event: &Event) -> std::io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.append(true).create(true);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(permissions::PRIVATE_FILE_MODE);
+        }
+
+        // A single write per line, appends of concurrent processes don't
+        // interleave
+        let mut file = options.open(&self.path)?;
+        file.write_all(format!("{}\n", event.format()).as_bytes())?;
+        file.sync_data()?;
+
+        Ok(())
+    }
+}
+
+fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buffer = [0u8; 256];
+        let result =
+            unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+        if result == 0 {
+            let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+            return String::from_utf8_lossy(&buffer[..len]).to_string();
+        }
+    }
+
+    env::var("COMPUTERNAME")
+        .or_else(|_| env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn username() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_path() {
+        let log = AuditLog::for_store(Path::new("/home/ova/.config/ova.store"));
+        assert_eq!(log.path(), Path::new("/home/ova/.config/ova.store.log"));
+    }
+
+    #[test]
+    fn test_record_and_read_events() {
+        let log = AuditLog::for_store(Path::new("test_audit.store"));
+
+        log.record("add", "github", "").unwrap();
+        log.record("rename", "github", "work/github").unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(log.path())
+            .unwrap()
+            .write_all(b"not an event\n")
+            .unwrap();
+
+        let events = log.events().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].action, "add");
+        assert_eq!(events[0].host, hostname());
+        assert_eq!(events[1].detail, "work/github");
+        assert!(events[1].involves("work/github"));
+        assert!(!events[0].involves("work/github"));
+
+        fs::remove_file(log.path()).unwrap();
+    }
+}
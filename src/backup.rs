@@ -0,0 +1,416 @@
+use crate::{
+    atomic,
+    error::{Context, Error, Result},
+    format::{Document, Entry},
+    permissions,
+    utils::canonical_path,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_BACKUP_COUNT: usize = 10;
+
+const BACKUP_DIR_NAME: &str = "ova-backups";
+
+const BACKUP_EXTENSION: &str = ".bak";
+
+/// Copy of a store file, named `<store file name>.<unix millis>.bak`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// Milliseconds since the Unix epoch
+    pub created: u128,
+}
+
+impl Backup {
+    pub fn read(&self) -> Result<Document> {
+        let content = fs::read_to_string(&self.path).with_context(|| {
+            format!("Error reading backup at - {}", self.path.to_string_lossy())
+        })?;
+
+        Ok(Document::parse(&content))
+    }
+}
+
+/// What restoring a backup would do to an entry of the current store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    Changed(String),
+}
+
+/// Rotating backups of one store file, kept in `ova-backups` next to it unless
+/// another directory is configured. Stores share a configured directory, each
+/// gets its own subdirectory named after its file name and a hash of its
+/// path, so stores with the same file name don't mix up their backups.
+pub struct Backups {
+    dir: PathBuf,
+    store_path: PathBuf,
+    count: usize,
+}
+
+impl Backups {
+    pub fn new(store_path: &Path, dir: Option<&Path>, count: usize) -> Self {
+        let dir = match dir {
+            Some(dir) => dir.join(store_dir_name(store_path)),
+            None => store_path
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(BACKUP_DIR_NAME),
+        };
+
+        Backups {
+            dir,
+            store_path: store_path.to_path_buf(),
+            count,
+        }
+    }
+
+    /// Backs up the store and drops the oldest backups beyond the configured
+    /// count. Nothing is written when backups are off, the store doesn't
+    /// exist yet or it's unchanged since the last backup.
+    pub fn create(&self) -> Result<Option<Backup>> {
+        if self.count == 0 || !self.store_path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read(&self.store_path).with_context(|| {
+            format!(
+                "Error reading store file at - {}",
+                self.store_path.to_string_lossy()
+            )
+        })?;
+
+        let backups = self.list()?;
+        if let Some(latest) = backups.first() {
+            if fs::read(&latest.path).is_ok_and(|latest| latest == content) {
+                return Ok(None);
+            }
+        }
+
+        permissions::create_private_dir(&self.dir)?;
+
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis())
+            .unwrap_or_default();
+        let backup = Backup {
+            path: self
+                .dir
+                .join(format!("{}{}{}", self.prefix(), created, BACKUP_EXTENSION)),
+            created,
+        };
+        atomic::write_file(&backup.path, &content)?;
+
+        for old in self.list()?.iter().skip(self.count) {
+            fs::remove_file(&old.path).with_context(|| {
+                format!("Error removing backup at - {}", old.path.to_string_lossy())
+            })?;
+        }
+
+        Ok(Some(backup))
+    }
+
+    /// Backups of the store, newest first
+    pub fn list(&self) -> Result<Vec<Backup>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let prefix = self.prefix();
+        let mut backups: Vec<Backup> = fs::read_dir(&self.dir)
+            .with_context(|| {
+                format!(
+                    "Error reading backup directory at - {}",
+                    self.dir.to_string_lossy()
+                )
+            })?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                let created = file_name
+                    .strip_prefix(&prefix)?
+                    .strip_suffix(BACKUP_EXTENSION)?
+                    .parse()
+                    .ok()?;
+
+                Some(Backup {
+                    path: entry.path(),
+                    created,
+                })
+            })
+            .collect();
+
+        backups.sort_by_key(|backup| std::cmp::Reverse(backup.created));
+        Ok(backups)
+    }
+
+    /// Backup number `id` as shown by `list`, 1 being the newest
+    pub fn get(&self, id: usize) -> Result<Backup> {
+        id.checked_sub(1)
+            .and_then(|index| self.list().ok()?.into_iter().nth(index))
+            .ok_or_else(|| Error::Invalid(format!("Backup {} does not exist", id)))
+    }
+
+    /// Replaces the store with `backup`, backing up the current store first
+    /// so the restore itself can be undone
+    pub fn restore(&self, backup: &Backup) -> Result<()> {
+        let content = fs::read(&backup.path).with_context(|| {
+            format!(
+                "Error reading backup at - {}",
+                backup.path.to_string_lossy()
+            )
+        })?;
+
+        self.create()?;
+        atomic::write_file(&self.store_path, &content)
+    }
+
+    /// Newest backup that differs from the store, i.e. the state before the
+    /// last change. Newer backups identical to the store, left behind by
+    /// commands that failed, are dropped on the way.
+    pub fn last_change(&self) -> Result<Option<Backup>> {
+        let content = fs::read(&self.store_path).with_context(|| {
+            format!(
+                "Error reading store file at - {}",
+                self.store_path.to_string_lossy()
+            )
+        })?;
+
+        for backup in self.list()? {
+            let read = fs::read(&backup.path).with_context(|| {
+                format!(
+                    "Error reading backup at - {}",
+                    backup.path.to_string_lossy()
+                )
+            })?;
+            if read != content {
+                return Ok(Some(backup));
+            }
+            fs::remove_file(&backup.path).with_context(|| {
+                format!(
+                    "Error removing backup at - {}",
+                    backup.path.to_string_lossy()
+                )
+            })?;
+        }
+
+        Ok(None)
+    }
+
+    /// Puts `backup` back in place of the store and removes it, so undoing
+    /// again goes one more change back
+    pub fn undo(&self, backup: &Backup) -> Result<()> {
+        let content = fs::read(&backup.path).with_context(|| {
+            format!(
+                "Error reading backup at - {}",
+                backup.path.to_string_lossy()
+            )
+        })?;
+
+        atomic::write_file(&self.store_path, &content)?;
+        fs::remove_file(&backup.path).with_context(|| {
+            format!(
+                "Error removing backup at - {}",
+                backup.path.to_string_lossy()
+            )
+        })
+    }
+
+    fn prefix(&self) -> String {
+        let file_name = self
+            .store_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        format!("{}.", file_name)
+    }
+}
+
+/// Entries that change when `current` is replaced by `backup`, in the order
+/// they appear in the store
+pub fn diff(current: &[Entry], backup: &[Entry]) -> Vec<Change> {
+    let mut changes: Vec<Change> = vec![];
+
+    for entry in current {
+        match backup.iter().find(|other| other.name == entry.name) {
+            None => changes.push(Change::Removed(entry.name.clone())),
+            Some(other) if other != entry => changes.push(Change::Changed(entry.name.clone())),
+            Some(_) => {}
+        }
+    }
+
+    for entry in backup {
+        if !current.iter().any(|other| other.name == entry.name) {
+            changes.push(Change::Added(entry.name.clone()));
+        }
+    }
+
+    changes
+}
+
+/// `<store file name>-<hash of its canonical path>`
+fn store_dir_name(store_path: &Path) -> String {
+    let path = canonical_path(store_path);
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    // FNV-1a, unlike the std hasher it stays the same across Rust releases
+    let hash = path
+        .to_string_lossy()
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+
+    format!("{}-{:016x}", file_name, hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_create_rotates_backups() {
+        let dir = PathBuf::new().join("test_backup_rotation");
+        let store_path = dir.join("ova.store");
+        fs::create_dir(&dir).unwrap();
+        let backups = Backups::new(&store_path, None, 2);
+
+        assert_eq!(backups.create().unwrap(), None);
+
+        for i in 0..3 {
+            fs::write(
+                &store_path,
+                format!("key{} = GEZDGNBVGY3TQOJQGEZDGNBVGY\n", i),
+            )
+            .unwrap();
+            assert!(backups.create().unwrap().is_some());
+            thread::sleep(Duration::from_millis(2));
+        }
+        // Unchanged since the last backup
+        assert_eq!(backups.create().unwrap(), None);
+
+        let list = backups.list().unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(
+            fs::read_to_string(&backups.get(1).unwrap().path).unwrap(),
+            "key2 = GEZDGNBVGY3TQOJQGEZDGNBVGY\n"
+        );
+        assert_eq!(list[1].path.parent().unwrap(), dir.join("ova-backups"));
+        assert!(backups.get(0).is_err());
+        assert!(backups.get(3).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_shared_backup_dir() {
+        let dir = PathBuf::new().join("test_backup_shared");
+        let shared = dir.join("backups");
+        let work = dir.join("work").join("ova.store");
+        let home = dir.join("home").join("ova.store");
+        fs::create_dir_all(work.parent().unwrap()).unwrap();
+        fs::create_dir_all(home.parent().unwrap()).unwrap();
+
+        let work_backups = Backups::new(&work, Some(&shared), 5);
+        let home_backups = Backups::new(&home, Some(&shared), 5);
+        fs::write(&work, "work = GEZDGNBVGY3TQOJQGEZDGNBVGY\n").unwrap();
+        fs::write(&home, "home = GEZDGNBVGY3TQOJQGEZDGNBVGY\n").unwrap();
+        work_backups.create().unwrap();
+        home_backups.create().unwrap();
+
+        let work_list = work_backups.list().unwrap();
+        assert_eq!(work_list.len(), 1);
+        assert_eq!(
+            fs::read_to_string(&work_list[0].path).unwrap(),
+            "work = GEZDGNBVGY3TQOJQGEZDGNBVGY\n"
+        );
+        assert_eq!(home_backups.list().unwrap().len(), 1);
+        assert_ne!(
+            work_list[0].path.parent(),
+            home_backups.list().unwrap()[0].path.parent()
+        );
+        assert!(work_list[0].path.starts_with(&shared));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore_and_diff() {
+        let dir = PathBuf::new().join("test_backup_restore");
+        let store_path = dir.join("ova.store");
+        fs::create_dir(&dir).unwrap();
+        let backups = Backups::new(&store_path, Some(&dir.join("elsewhere")), 5);
+
+        fs::write(
+            &store_path,
+            "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\ngitlab = GEZDGNBVGY3TQOJQGEZDGNBVGY\n",
+        )
+        .unwrap();
+        let backup = backups.create().unwrap().unwrap();
+        fs::write(
+            &store_path,
+            "github = MFRGGZDFMZTWQ2LKNNWG23TPOA\naws = GEZDGNBVGY3TQOJQGEZDGNBVGY\n",
+        )
+        .unwrap();
+
+        let current = Document::parse(&fs::read_to_string(&store_path).unwrap()).entries();
+        let changes = diff(&current, &backup.read().unwrap().entries());
+        assert_eq!(
+            changes,
+            vec![
+                Change::Changed("github".to_string()),
+                Change::Removed("aws".to_string()),
+                Change::Added("gitlab".to_string()),
+            ]
+        );
+
+        thread::sleep(Duration::from_millis(2));
+        backups.restore(&backup).unwrap();
+        assert_eq!(
+            fs::read_to_string(&store_path).unwrap(),
+            "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\ngitlab = GEZDGNBVGY3TQOJQGEZDGNBVGY\n"
+        );
+        assert_eq!(backups.list().unwrap().len(), 2);
+        assert_eq!(backups.list().unwrap()[1], backup);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_undo() {
+        let dir = PathBuf::new().join("test_backup_undo");
+        let store_path = dir.join("ova.store");
+        fs::create_dir(&dir).unwrap();
+        let backups = Backups::new(&store_path, None, 5);
+
+        fs::write(&store_path, "first").unwrap();
+        backups.create().unwrap();
+        thread::sleep(Duration::from_millis(2));
+        fs::write(&store_path, "second").unwrap();
+        backups.create().unwrap();
+        fs::write(&store_path, "third").unwrap();
+        thread::sleep(Duration::from_millis(2));
+        // A failed command backs up the store without changing it
+        backups.create().unwrap();
+
+        let backup = backups.last_change().unwrap().unwrap();
+        backups.undo(&backup).unwrap();
+        assert_eq!(fs::read_to_string(&store_path).unwrap(), "second");
+
+        let backup = backups.last_change().unwrap().unwrap();
+        backups.undo(&backup).unwrap();
+        assert_eq!(fs::read_to_string(&store_path).unwrap(), "first");
+        assert_eq!(backups.last_change().unwrap(), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
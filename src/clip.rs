@@ -1,7 +1,21 @@
-use anyhow::{anyhow, Result};
+use crate::error::{Error, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use std::env;
 use std::env::consts::OS;
-use std::process::Command;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardProvider {
+    /// Detect from the running OS and session
+    Auto,
+    Wayland,
+    X11,
+    Windows,
+    Macos,
+}
 
 pub struct Clip {
     pub text: Option<String>,
@@ -40,13 +54,33 @@ impl Clip {
                 clip.is_mac = Some(true);
             }
             _ => {
-                return Err(anyhow!("Unsupported OS"));
+                return Err(Error::Clipboard("Unsupported OS".to_string()));
             }
         }
 
         Ok(clip)
     }
 
+    pub fn with_provider(provider: ClipboardProvider) -> Result<Self> {
+        let mut clip = Self {
+            text: None,
+            is_wayland: None,
+            is_x11: None,
+            is_windows: None,
+            is_mac: None,
+        };
+
+        match provider {
+            ClipboardProvider::Auto => return Self::new(),
+            ClipboardProvider::Wayland => clip.is_wayland = Some(true),
+            ClipboardProvider::X11 => clip.is_x11 = Some(true),
+            ClipboardProvider::Windows => clip.is_windows = Some(true),
+            ClipboardProvider::Macos => clip.is_mac = Some(true),
+        }
+
+        Ok(clip)
+    }
+
     pub fn copy(&mut self, text: &str) -> Result<()> {
         match self {
             Clip {
@@ -72,7 +106,7 @@ impl Clip {
                 self.copy_macos(text)?;
             }
             _ => {
-                return Err(anyhow!("Unsupported OS"));
+                return Err(Error::Clipboard("Unsupported OS".to_string()));
             }
         }
 
@@ -80,51 +114,196 @@ impl Clip {
     }
 
     pub fn copy_x11(&mut self, text: &str) -> Result<()> {
-        let mut child = Command::new("sh")
-            .arg("-c")
-            .arg(format!("echo -n {} | xclip -selection clipboard", text))
-            .spawn()
-            .expect("failed to execute process");
-        child.wait().expect("failed to wait on child");
+        let mut command = Command::new("xclip");
+        command.arg("-selection").arg("clipboard");
 
-        Ok(())
+        Self::run_with_input(command, "xclip", Some(text))
     }
 
     pub fn copy_wayland(&mut self, text: &str) -> Result<()> {
-        let mut child = Command::new("sh")
-            .arg("-c")
-            .arg(format!("wl-copy {}", text))
-            .spawn()
-            .expect("failed to execute process");
+        let mut command = Command::new("wl-copy");
+        command.arg("--").arg(text);
+
+        Self::run(command, "wl-copy")
+    }
+
+    pub fn copy_windows(&mut self, text: &str) -> Result<()> {
+        Self::run_with_input(Command::new("clip.exe"), "clip.exe", Some(text))
+    }
+
+    pub fn copy_macos(&mut self, text: &str) -> Result<()> {
+        Self::run_with_input(Command::new("pbcopy"), "pbcopy", Some(text))
+    }
 
-        child.wait().expect("failed to wait on child");
+    pub fn type_text(&mut self, text: &str, enter: bool) -> Result<()> {
+        match self {
+            Clip {
+                is_wayland: Some(true),
+                ..
+            } => {
+                self.type_wayland(text, enter)?;
+            }
+            Clip {
+                is_x11: Some(true), ..
+            } => {
+                self.type_x11(text, enter)?;
+            }
+            Clip {
+                is_windows: Some(true),
+                ..
+            } => {
+                self.type_windows(text, enter)?;
+            }
+            Clip {
+                is_mac: Some(true), ..
+            } => {
+                self.type_macos(text, enter)?;
+            }
+            _ => {
+                return Err(Error::Clipboard("Unsupported OS".to_string()));
+            }
+        }
 
         Ok(())
     }
 
-    pub fn copy_windows(&mut self, text: &str) -> Result<()> {
-        let mut child = Command::new("powershell.exe")
-            .arg("-c")
-            .arg(format!("Set-Clipboard -Value {}", text))
-            .spawn()
-            .expect("failed to execute process");
+    pub fn type_wayland(&mut self, text: &str, enter: bool) -> Result<()> {
+        // wtype needs the virtual-keyboard protocol which not every compositor
+        // implements, ydotool works everywhere as long as ydotoold is running
+        if Self::has_command("wtype") {
+            Self::run(Self::wtype_command(text, enter), "wtype")
+        } else {
+            self.type_ydotool(text, enter)
+        }
+    }
 
-        child.wait().expect("failed to wait on child");
+    pub fn type_x11(&mut self, text: &str, enter: bool) -> Result<()> {
+        for command in Self::xdotool_commands(text, enter) {
+            Self::run(command, "xdotool")?;
+        }
 
         Ok(())
     }
 
-    pub fn copy_macos(&mut self, text: &str) -> Result<()> {
-        let mut child = Command::new("sh")
+    fn wtype_command(text: &str, enter: bool) -> Command {
+        let mut command = Command::new("wtype");
+        // Everything after `--` is typed as text, so the key goes before it
+        if enter {
+            command.arg("-k").arg("Return");
+        }
+        command.arg("--").arg(text);
+        command
+    }
+
+    fn xdotool_commands(text: &str, enter: bool) -> Vec<Command> {
+        // `xdotool type` takes all remaining arguments as text, the key press
+        // needs a command of its own
+        let mut type_text = Command::new("xdotool");
+        type_text.arg("type").arg("--").arg(text);
+        let mut commands = vec![type_text];
+
+        if enter {
+            let mut key = Command::new("xdotool");
+            key.arg("key").arg("Return");
+            commands.push(key);
+        }
+
+        commands
+    }
+
+    pub fn type_ydotool(&mut self, text: &str, enter: bool) -> Result<()> {
+        let mut command = Command::new("ydotool");
+        command.arg("type").arg("--");
+        if enter {
+            command.arg(format!("{}\n", text));
+        } else {
+            command.arg(text);
+        }
+
+        Self::run(command, "ydotool")
+    }
+
+    pub fn type_windows(&mut self, text: &str, enter: bool) -> Result<()> {
+        let keys = if enter {
+            format!("{}{{ENTER}}", text)
+        } else {
+            text.to_string()
+        };
+
+        let mut command = Command::new("powershell.exe");
+        command.arg("-c").arg(format!(
+            "(New-Object -ComObject WScript.Shell).SendKeys('{}')",
+            keys
+        ));
+
+        Self::run(command, "powershell.exe")
+    }
+
+    pub fn type_macos(&mut self, text: &str, enter: bool) -> Result<()> {
+        let mut script = format!(
+            "tell application \"System Events\" to keystroke \"{}\"",
+            text
+        );
+        if enter {
+            script.push_str("\ntell application \"System Events\" to key code 36");
+        }
+
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(script);
+
+        Self::run(command, "osascript")
+    }
+
+    fn has_command(program: &str) -> bool {
+        Command::new("sh")
             .arg("-c")
-            .arg(format!(
-                "echo -n {} | osascript -e 'set the clipboard to (read stdin)'",
-                text
-            ))
-            .spawn()
-            .expect("failed to execute process");
+            .arg(format!("command -v {} >/dev/null 2>&1", program))
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    }
+
+    fn run(command: Command, program: &str) -> Result<()> {
+        Self::run_with_input(command, program, None)
+    }
+
+    /// Runs `program`, writing `input` to its stdin, and fails unless it
+    /// exits successfully
+    fn run_with_input(mut command: Command, program: &str, input: Option<&str>) -> Result<()> {
+        if input.is_some() {
+            command.stdin(Stdio::piped());
+        }
+
+        let mut child = command.spawn().map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::Clipboard(format!("{} is not installed", program)),
+            _ => Error::Clipboard(format!("Failed to execute {}: {}", program, e)),
+        })?;
 
-        child.wait().expect("failed to wait on child");
+        if let Some(input) = input {
+            // Dropping stdin closes it, so the program sees the end of input
+            let written = child
+                .stdin
+                .take()
+                .map(|mut stdin| stdin.write_all(input.as_bytes()));
+            if let Some(Err(e)) = written {
+                let _ = child.wait();
+                return Err(Error::Clipboard(format!(
+                    "Failed to write to {}: {}",
+                    program, e
+                )));
+            }
+        }
+
+        let status = child
+            .wait()
+            .map_err(|e| Error::Clipboard(format!("Failed to wait for {}: {}", program, e)))?;
+
+        if !status.success() {
+            return Err(Error::Clipboard(format!(
+                "{} exited with {}",
+                program, status
+            )));
+        }
 
         Ok(())
     }
@@ -171,4 +350,79 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_clip_type_unsupported() -> Result<()> {
+        let mut clip = Clip {
+            text: None,
+            is_wayland: None,
+            is_x11: None,
+            is_windows: None,
+            is_mac: None,
+        };
+
+        assert!(clip.type_text("123456", false).is_err());
+        assert!(clip.type_text("123456", true).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_reports_failures() {
+        let err = Clip::run_with_input(
+            Command::new("ova-missing-clipboard-tool"),
+            "ova-missing-clipboard-tool",
+            Some("123456"),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Clipboard(_)));
+        assert_eq!(
+            err.to_string(),
+            "ova-missing-clipboard-tool is not installed"
+        );
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("test \"$(cat)\" = 123456");
+        assert!(Clip::run_with_input(command, "sh", Some("123456")).is_ok());
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("exit 3");
+        assert!(matches!(Clip::run(command, "sh"), Err(Error::Clipboard(_))));
+    }
+
+    #[test]
+    fn test_typing_commands() {
+        let args = |command: &Command| -> Vec<String> {
+            command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(
+            args(&Clip::wtype_command("123456", true)),
+            vec!["-k", "Return", "--", "123456"]
+        );
+        assert_eq!(
+            args(&Clip::wtype_command("-123", false)),
+            vec!["--", "-123"]
+        );
+
+        let commands = Clip::xdotool_commands("123456", true);
+        assert_eq!(
+            commands.iter().map(args).collect::<Vec<_>>(),
+            vec![vec!["type", "--", "123456"], vec!["key", "Return"]]
+        );
+        assert_eq!(Clip::xdotool_commands("123456", false).len(), 1);
+    }
+
+    #[test]
+    fn test_clip_with_provider() -> Result<()> {
+        let clip = Clip::with_provider(ClipboardProvider::X11)?;
+        assert_eq!(clip.is_x11, Some(true));
+        assert!(clip.is_wayland.is_none() && clip.is_windows.is_none() && clip.is_mac.is_none());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,439 @@
+use crate::{
+    atomic,
+    clip::ClipboardProvider,
+    error::{Context, Error, Result},
+    menu::Launcher,
+    utils::parse_digits,
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::env::consts::OS;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CONFIG_DIR_NAME: &str = "ova";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+pub const CONFIG_KEYS: [&str; 13] = [
+    "copy_mode",
+    "clipboard",
+    "store",
+    "format",
+    "digits",
+    "launcher",
+    "launcher_command",
+    "default_vault",
+    "lock_timeout",
+    "backup_count",
+    "backup_dir",
+    "log_codes",
+    "time_offset",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CopyMode {
+    Print,
+    Copy,
+    Type,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Human readable output
+    Text,
+    /// Bare values only, for scripts
+    Plain,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copy_mode: Option<CopyMode>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clipboard: Option<ClipboardProvider>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<PathBuf>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digits: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub launcher: Option<Launcher>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub launcher_command: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_vault: Option<String>,
+
+    /// Seconds to wait for another ova process to release the store
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock_timeout: Option<u64>,
+
+    /// Number of store backups to keep, 0 turns backups off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_count: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Also record code retrievals in the audit log
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_codes: Option<bool>,
+
+    /// Seconds added to the system time when generating codes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_offset: Option<i64>,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vaults: BTreeMap<String, VaultConfig>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VaultConfig {
+    pub store: PathBuf,
+
+    /// Overrides the global digits for codes from this vault
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digits: Option<usize>,
+
+    /// Overrides the global time offset for codes from this vault
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_offset: Option<i64>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/ova/config.toml`, falling back to `~/.config/ova/config.toml`
+    pub fn default_path() -> Result<PathBuf> {
+        let config_dir =
+            match OS {
+                "linux" | "macos" => match env::var("XDG_CONFIG_HOME") {
+                    Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+                    _ => PathBuf::from(env::var("HOME").map_err(|_| {
+                        Error::Config("Error getting HOME env variable".to_string())
+                    })?)
+                    .join(".config"),
+                },
+                "windows" => PathBuf::from(env::var("USERPROFILE").map_err(|_| {
+                    Error::Config("Error getting USERPROFILE env variable".to_string())
+                })?)
+                .join("AppData/Local"),
+                _ => return Err(Error::Config("Unsupported OS".to_string())),
+            };
+
+        Ok(config_dir.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME))
+    }
+
+    /// Loads the config, a missing file is the same as an empty one
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let content = fs::read_to_string(path).with_context(|| {
+            format!("Error reading config file at - {}", path.to_string_lossy())
+        })?;
+
+        toml::from_str(&content).map_err(|err| {
+            Error::Config(format!(
+                "Error parsing config file at - {}\n\n{}",
+                path.to_string_lossy(),
+                err
+            ))
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| {
+                format!("Error creating directory at - {}", dir.to_string_lossy())
+            })?;
+        }
+
+        let content = toml::to_string(self)
+            .map_err(|err| Error::Config(format!("Error serializing config - {}", err)))?;
+        atomic::write_file(path, content.as_bytes())
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let value = match key {
+            "copy_mode" => self.copy_mode.map(|v| value_name(&v)),
+            "clipboard" => self.clipboard.map(|v| value_name(&v)),
+            "store" => self.store.as_ref().map(|v| v.to_string_lossy().to_string()),
+            "format" => self.format.map(|v| value_name(&v)),
+            "digits" => self.digits.map(|v| v.to_string()),
+            "launcher" => self.launcher.map(|v| value_name(&v)),
+            "launcher_command" => self.launcher_command.clone(),
+            "default_vault" => self.default_vault.clone(),
+            "lock_timeout" => self.lock_timeout.map(|v| v.to_string()),
+            "backup_count" => self.backup_count.map(|v| v.to_string()),
+            "backup_dir" => self
+                .backup_dir
+                .as_ref()
+                .map(|v| v.to_string_lossy().to_string()),
+            "log_codes" => self.log_codes.map(|v| v.to_string()),
+            "time_offset" => self.time_offset.map(|v| v.to_string()),
+            _ => return Err(unknown_key(key)),
+        };
+
+        Ok(value)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "copy_mode" => self.copy_mode = Some(parse_value(key, value)?),
+            "clipboard" => self.clipboard = Some(parse_value(key, value)?),
+            "store" => {
+                if value.trim().is_empty() {
+                    return Err(Error::Config("store cannot be empty".to_string()));
+                }
+                self.store = Some(PathBuf::from(value));
+            }
+            "format" => self.format = Some(parse_value(key, value)?),
+            "digits" => self.digits = Some(parse_digits(value)?),
+            "launcher" => self.launcher = Some(parse_value(key, value)?),
+            "launcher_command" => {
+                if value.trim().is_empty() {
+                    return Err(Error::Config(
+                        "launcher_command cannot be empty".to_string(),
+                    ));
+                }
+                self.launcher_command = Some(value.to_string());
+            }
+            "default_vault" => {
+                self.vault(value)?;
+                self.default_vault = Some(value.to_string());
+            }
+            "lock_timeout" => {
+                let timeout: u64 = value.parse().map_err(|_| {
+                    Error::Config("lock_timeout must be a number of seconds".to_string())
+                })?;
+                self.lock_timeout = Some(timeout);
+            }
+            "backup_count" => {
+                let count: usize = value
+                    .parse()
+                    .map_err(|_| Error::Config("backup_count must be a number".to_string()))?;
+                self.backup_count = Some(count);
+            }
+            "backup_dir" => {
+                if value.trim().is_empty() {
+                    return Err(Error::Config("backup_dir cannot be empty".to_string()));
+                }
+                self.backup_dir = Some(PathBuf::from(value));
+            }
+            "log_codes" => {
+                let log_codes: bool = value
+                    .parse()
+                    .map_err(|_| Error::Config("log_codes must be true or false".to_string()))?;
+                self.log_codes = Some(log_codes);
+            }
+            "time_offset" => {
+                let offset: i64 = value.parse().map_err(|_| {
+                    Error::Config("time_offset must be a number of seconds".to_string())
+                })?;
+                self.time_offset = Some(offset);
+            }
+            _ => return Err(unknown_key(key)),
+        }
+
+        Ok(())
+    }
+
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        match key {
+            "copy_mode" => self.copy_mode = None,
+            "clipboard" => self.clipboard = None,
+            "store" => self.store = None,
+            "format" => self.format = None,
+            "digits" => self.digits = None,
+            "launcher" => self.launcher = None,
+            "launcher_command" => self.launcher_command = None,
+            "default_vault" => self.default_vault = None,
+            "lock_timeout" => self.lock_timeout = None,
+            "backup_count" => self.backup_count = None,
+            "backup_dir" => self.backup_dir = None,
+            "log_codes" => self.log_codes = None,
+            "time_offset" => self.time_offset = None,
+            _ => return Err(unknown_key(key)),
+        }
+
+        Ok(())
+    }
+
+    pub fn vault(&self, name: &str) -> Result<&VaultConfig> {
+        self.vaults
+            .get(name)
+            .ok_or_else(|| Error::Config(format!("Vault {} does not exist", name)))
+    }
+
+    pub fn add_vault(&mut self, name: &str, store: PathBuf) -> Result<()> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(Error::Config(
+                "Vault name can only contain letters, digits, '-' and '_'".to_string(),
+            ));
+        }
+
+        if self.vaults.contains_key(name) {
+            return Err(Error::Config(format!("Vault {} already exists", name)));
+        }
+
+        self.vaults.insert(
+            name.to_string(),
+            VaultConfig {
+                store,
+                digits: None,
+                time_offset: None,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn remove_vault(&mut self, name: &str) -> Result<VaultConfig> {
+        let vault = self
+            .vaults
+            .remove(name)
+            .ok_or_else(|| Error::Config(format!("Vault {} does not exist", name)))?;
+
+        if self.default_vault.as_deref() == Some(name) {
+            self.default_vault = None;
+        }
+
+        Ok(vault)
+    }
+}
+
+fn value_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn parse_value<T: ValueEnum>(key: &str, value: &str) -> Result<T> {
+    T::from_str(value, true).map_err(|_| {
+        let possible: Vec<String> = T::value_variants().iter().map(value_name).collect();
+        Error::Config(format!(
+            "Invalid value '{}' for {}, expected one of: {}",
+            value,
+            key,
+            possible.join(", ")
+        ))
+    })
+}
+
+fn unknown_key(key: &str) -> Error {
+    Error::Config(format!(
+        "Unknown config key '{}', expected one of: {}",
+        key,
+        CONFIG_KEYS.join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::remove_file;
+
+    #[test]
+    fn test_load_missing_config() {
+        let path = PathBuf::new().join("test_missing_config.toml");
+
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_save_and_load_config() {
+        let path = PathBuf::new().join("test_save_config.toml");
+        let mut config = Config::default();
+        config.set("copy_mode", "copy").unwrap();
+        config.set("digits", "8").unwrap();
+        config.set("clipboard", "wayland").unwrap();
+
+        config.save(&path).unwrap();
+        let loaded = Config::load(&path).unwrap();
+        assert_eq!(loaded, config);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_config_get_set_unset() {
+        let mut config = Config::default();
+        assert_eq!(config.get("format").unwrap(), None);
+
+        config.set("format", "plain").unwrap();
+        assert_eq!(config.get("format").unwrap(), Some("plain".to_string()));
+
+        config.unset("format").unwrap();
+        assert_eq!(config.get("format").unwrap(), None);
+    }
+
+    #[test]
+    fn test_config_set_invalid_values() {
+        let mut config = Config::default();
+
+        assert!(config.set("digits", "5").is_err());
+        assert!(config.set("digits", "six").is_err());
+        assert!(config.set("copy_mode", "paste").is_err());
+        assert!(config.set("unknown", "value").is_err());
+        assert!(config.get("unknown").is_err());
+    }
+
+    #[test]
+    fn test_config_vaults() {
+        let path = PathBuf::new().join("test_vaults_config.toml");
+        let mut config = Config::default();
+        config.set("digits", "6").unwrap();
+
+        config
+            .add_vault("work", PathBuf::from("work.store"))
+            .unwrap();
+        assert!(config
+            .add_vault("work", PathBuf::from("other.store"))
+            .is_err());
+        assert!(config
+            .add_vault("not/valid", PathBuf::from("x.store"))
+            .is_err());
+        assert!(config.set("default_vault", "personal").is_err());
+
+        config.set("default_vault", "work").unwrap();
+        config.save(&path).unwrap();
+        let mut loaded = Config::load(&path).unwrap();
+        assert_eq!(loaded, config);
+        assert_eq!(
+            loaded.vault("work").unwrap().store,
+            PathBuf::from("work.store")
+        );
+
+        loaded.remove_vault("work").unwrap();
+        assert!(loaded.default_vault.is_none());
+        assert!(loaded.remove_vault("work").is_err());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_invalid_config() {
+        let path = PathBuf::new().join("test_invalid_config.toml");
+        fs::write(&path, "digits = \"six\"").unwrap();
+
+        assert!(Config::load(&path).is_err());
+
+        remove_file(path).unwrap();
+    }
+}
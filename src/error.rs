@@ -0,0 +1,143 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything the library can fail with. The binary maps each variant to its
+/// own exit code.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Keys an operation names that aren't in the store
+    KeyNotFound(Vec<String>),
+    /// A key that is about to be created already exists
+    KeyExists(String),
+    /// The store file exists but can't be read as a store
+    CorruptStore { path: PathBuf, reason: String },
+    /// Another process holds the store lock, with its pid when known
+    Locked(Option<u32>),
+    /// The store or its directory belongs to another user
+    Ownership { path: PathBuf, uid: u32 },
+    /// A secret that isn't valid or a code that can't be generated from it
+    Otp(String),
+    /// A code given to verify matches none of the codes in the window
+    CodeMismatch { name: String, window: u64 },
+    /// No clipboard tool is available or it failed
+    Clipboard(String),
+    /// Invalid config value or config file
+    Config(String),
+    /// The menu launcher failed or nothing was picked
+    Menu(String),
+    /// The agent refused a request or couldn't be reached
+    Agent(String),
+    /// A time server sent an answer that can't be used
+    Ntp(String),
+    /// Invalid input, e.g. an empty key name
+    Invalid(String),
+    /// A file, directory or socket operation failed
+    Io { context: String, source: io::Error },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::KeyNotFound(names) => match names.as_slice() {
+                [name] => write!(f, "Key {} not found", name),
+                names => write!(f, "Keys {} not found", names.join(", ")),
+            },
+            Error::KeyExists(name) => write!(f, "Key {} already exists", name),
+            Error::CorruptStore { path, reason } => write!(
+                f,
+                "Store at {} is corrupt: {}",
+                path.to_string_lossy(),
+                reason
+            ),
+            Error::Locked(Some(pid)) => write!(f, "Store is locked by pid {}", pid),
+            Error::Locked(None) => write!(f, "Store is locked by another process"),
+            Error::Ownership { path, uid } => write!(
+                f,
+                "{} is owned by another user (uid {}), refusing to use it",
+                path.to_string_lossy(),
+                uid
+            ),
+            Error::CodeMismatch { name, window } => write!(
+                f,
+                "Code does not match {} within {} time steps",
+                name, window
+            ),
+            Error::Otp(message)
+            | Error::Clipboard(message)
+            | Error::Config(message)
+            | Error::Menu(message)
+            | Error::Agent(message)
+            | Error::Ntp(message)
+            | Error::Invalid(message) => write!(f, "{}", message),
+            Error::Io { context, .. } => write!(f, "{}", context),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Turns I/O errors into [`Error::Io`] with a description of what was being
+/// done
+pub(crate) trait Context<T> {
+    fn with_context<C, F>(self, context: F) -> Result<T>
+    where
+        C: Into<String>,
+        F: FnOnce() -> C;
+}
+
+impl<T> Context<T> for std::result::Result<T, io::Error> {
+    fn with_context<C, F>(self, context: F) -> Result<T>
+    where
+        C: Into<String>,
+        F: FnOnce() -> C,
+    {
+        self.map_err(|source| Error::Io {
+            context: context().into(),
+            source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Error::KeyNotFound(vec!["github".to_string()]).to_string(),
+            "Key github not found"
+        );
+        assert_eq!(
+            Error::KeyNotFound(vec!["github".to_string(), "gitlab".to_string()]).to_string(),
+            "Keys github, gitlab not found"
+        );
+        assert_eq!(
+            Error::Locked(Some(42)).to_string(),
+            "Store is locked by pid 42"
+        );
+    }
+
+    #[test]
+    fn test_io_context() {
+        let result: io::Result<()> = Err(io::Error::new(io::ErrorKind::NotFound, "missing"));
+        let err = result.with_context(|| "Error reading store").unwrap_err();
+
+        assert_eq!(err.to_string(), "Error reading store");
+        assert_eq!(
+            std::error::Error::source(&err).unwrap().to_string(),
+            "missing"
+        );
+    }
+}
@@ -0,0 +1,677 @@
+//! On-disk format of the store file.
+//!
+//! Entries are written as `NAME = VALUE`, one per line. Lines starting with `#`
+//! are comments. Both sides are trimmed, so whitespace around `=` is free.
+//!
+//! A name or value that can't be written as is gets wrapped in double quotes,
+//! e.g. `"work = prod" = JBSWY3DPEHPK3PXP`. That's the case when it contains
+//! `=` (names only), starts with `#` or `"`, has leading or trailing
+//! whitespace, contains control characters or is empty. Inside quotes `\\`,
+//! `\"`, `\n`, `\r` and `\t` are escapes, any other character is literal.
+//! Unquoted names and values are taken literally, so existing stores read the
+//! same as before.
+//!
+//! Indented `KEY = VALUE` lines right below an entry are attributes of that
+//! entry, e.g. its tags, when `KEY` is one of [`ATTRIBUTES`]. They follow the
+//! same quoting rules and a key may repeat for attributes holding several
+//! values. ova uses `tags`, `note`, `recovery`, `recovery_used`, `deleted`,
+//! `type`, the kind of codes the entry generates, which is left out for
+//! plain TOTP, and `digits` for keys whose codes don't have the configured
+//! number of digits. Any other indented line is an entry of its own, so stores
+//! with hand-indented entries read the same as before.
+//!
+//! Removed entries stay in the file with a `deleted` attribute holding the
+//! time they were removed. They're in the trash, so lookups by name skip them
+//! and a new entry may reuse the name.
+//!
+//! Anything else in the file, comments, blank lines and lines that don't
+//! parse, is kept verbatim when the store is rewritten.
+
+use crate::error::{Error, Result};
+use crate::otp::OtpType;
+use crate::secret::Secret;
+use std::cmp::Reverse;
+use std::fmt;
+use std::ops::Range;
+use zeroize::Zeroize;
+
+const ATTRIBUTE_INDENT: &str = "    ";
+
+/// Attribute marking an entry as being in the trash
+pub const DELETED: &str = "deleted";
+
+/// Keys of the indented lines read as attributes of the entry above them
+pub const ATTRIBUTES: &[&str] = &[
+    "tags",
+    "note",
+    "recovery",
+    "recovery_used",
+    DELETED,
+    "type",
+    "digits",
+];
+
+/// An entry with the attributes listed below it
+#[derive(Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub value: Secret,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Entry {
+    /// All values of the attribute `key`
+    pub fn attribute(&self, key: &str) -> Vec<&str> {
+        self.attributes
+            .iter()
+            .filter(|(attribute, _)| attribute == key)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn tags(&self) -> Vec<String> {
+        self.attribute("tags")
+            .iter()
+            .flat_map(|tags| tags.split(','))
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect()
+    }
+
+    /// Free-form note kept with the entry, newlines are stored escaped
+    pub fn note(&self) -> Option<&str> {
+        self.attribute("note").into_iter().next()
+    }
+
+    /// Recovery codes that haven't been used yet, in the order they were added
+    pub fn recovery_codes(&self) -> Vec<&str> {
+        self.attribute("recovery")
+    }
+
+    pub fn used_recovery_codes(&self) -> Vec<&str> {
+        self.attribute("recovery_used")
+    }
+
+    /// Kind of codes the entry generates, TOTP unless its `type` says
+    /// otherwise
+    pub fn otp_type(&self) -> Result<OtpType> {
+        self.attribute("type")
+            .into_iter()
+            .next()
+            .map_or(Ok(OtpType::Totp), str::parse)
+    }
+
+    /// Digits of the entry's codes when it has its own, overriding the
+    /// configured ones
+    pub fn digits(&self) -> Result<Option<usize>> {
+        self.attribute("digits")
+            .into_iter()
+            .next()
+            .map(|digits| {
+                digits
+                    .parse()
+                    .map_err(|_| Error::Otp(format!("Invalid digits '{}'", digits)))
+            })
+            .transpose()
+    }
+
+    /// When the entry was moved to the trash, in seconds since the Unix epoch
+    pub fn deleted(&self) -> Option<u64> {
+        self.attribute(DELETED)
+            .into_iter()
+            .next()
+            .map(|deleted| deleted.parse().unwrap_or_default())
+    }
+
+    /// Folder part of a slash separated name, `work/aws` for `work/aws/prod`
+    pub fn folder(&self) -> Option<&str> {
+        self.name.rsplit_once('/').map(|(folder, _)| folder)
+    }
+
+    /// Whether the entry is in `folder` or one of its subfolders
+    pub fn in_folder(&self, folder: &str) -> bool {
+        let folder = folder.trim_matches('/');
+        folder.is_empty()
+            || self
+                .folder()
+                .is_some_and(|own| own == folder || own.starts_with(&format!("{}/", folder)))
+    }
+}
+
+/// Every line of a store file as it is on disk, so a rewrite only touches
+/// the lines of the entries that actually changed
+#[derive(Clone, PartialEq, Eq)]
+pub struct Document {
+    pub lines: Vec<String>,
+    line_ending: &'static str,
+    trailing_newline: bool,
+}
+
+impl Document {
+    pub fn parse(content: &str) -> Self {
+        Document {
+            lines: content.lines().map(|line| line.to_string()).collect(),
+            line_ending: if content.contains("\r\n") {
+                "\r\n"
+            } else {
+                "\n"
+            },
+            trailing_newline: content.ends_with('\n'),
+        }
+    }
+
+    /// Index of the line holding the entry called exactly `name`
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.entry_range(name).map(|range| range.start)
+    }
+
+    /// Lines making up the entry called exactly `name`, its own line followed
+    /// by its attributes
+    pub fn entry_range(&self, name: &str) -> Option<Range<usize>> {
+        self.live_blocks()
+            .into_iter()
+            .find(|(_, entry)| entry.name == name)
+            .map(|(range, _)| range)
+    }
+
+    pub fn entry(&self, name: &str) -> Option<Entry> {
+        self.live_blocks()
+            .into_iter()
+            .find(|(_, entry)| entry.name == name)
+            .map(|(_, entry)| entry)
+    }
+
+    /// All entries in the order they appear in the file, without the ones
+    /// in the trash
+    pub fn entries(&self) -> Vec<Entry> {
+        self.live_blocks()
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect()
+    }
+
+    /// Entries in the trash with their lines, most recently deleted first
+    pub fn trashed(&self) -> Vec<(Range<usize>, Entry)> {
+        let mut trashed: Vec<(Range<usize>, Entry)> = self
+            .blocks()
+            .into_iter()
+            .filter(|(_, entry)| entry.deleted().is_some())
+            .collect();
+
+        trashed.sort_by_key(|(range, entry)| Reverse((entry.deleted(), range.start)));
+        trashed
+    }
+
+    /// Takes the trashed entry at `range` out of the trash
+    pub fn untrash(&mut self, range: Range<usize>) {
+        let start = range.start;
+        let kept: Vec<String> = self.lines[range.clone()]
+            .iter()
+            .enumerate()
+            .filter(|(i, line)| {
+                *i == 0 || parse_entry(line).is_none_or(|(attribute, _)| attribute != DELETED)
+            })
+            .map(|(_, line)| line.clone())
+            .collect();
+
+        self.lines.splice(start..range.end, kept);
+    }
+
+    /// Replaces every `key` attribute of the entry `name` with one line per
+    /// value, leaving its other attributes alone. Returns false if there's no
+    /// such entry.
+    pub fn set_attribute(&mut self, name: &str, key: &str, values: &[String]) -> bool {
+        debug_assert!(ATTRIBUTES.contains(&key), "{} is not an attribute", key);
+
+        let range = match self.entry_range(name) {
+            Some(range) => range,
+            None => return false,
+        };
+
+        let mut kept: Vec<String> = self.lines[range.clone()]
+            .iter()
+            .enumerate()
+            .filter(|(i, line)| {
+                *i == 0 || parse_entry(line).is_none_or(|(attribute, _)| attribute != key)
+            })
+            .map(|(_, line)| line.clone())
+            .collect();
+
+        kept.extend(
+            values
+                .iter()
+                .map(|value| format!("{}{}", ATTRIBUTE_INDENT, format_entry(key, value))),
+        );
+
+        self.lines.splice(range, kept);
+        true
+    }
+
+    fn live_blocks(&self) -> Vec<(Range<usize>, Entry)> {
+        self.blocks()
+            .into_iter()
+            .filter(|(_, entry)| entry.deleted().is_none())
+            .collect()
+    }
+
+    fn blocks(&self) -> Vec<(Range<usize>, Entry)> {
+        let mut blocks: Vec<(Range<usize>, Entry)> = vec![];
+        let mut in_entry = false;
+
+        for (i, line) in self.lines.iter().enumerate() {
+            let parsed = parse_entry(line);
+            let indented = line.starts_with([' ', '\t']);
+
+            match (parsed, blocks.last_mut()) {
+                (Some((key, value)), Some((range, entry)))
+                    if in_entry && indented && ATTRIBUTES.contains(&key.as_str()) =>
+                {
+                    range.end = i + 1;
+                    entry.attributes.push((key, value));
+                }
+                (Some((name, value)), _) => {
+                    in_entry = true;
+                    blocks.push((
+                        i..i + 1,
+                        Entry {
+                            name,
+                            value: Secret::new(value),
+                            attributes: vec![],
+                        },
+                    ));
+                }
+                (None, _) => in_entry = false,
+            }
+        }
+
+        blocks
+    }
+
+    pub fn push(&mut self, line: String) {
+        self.lines.push(line);
+        self.trailing_newline = true;
+    }
+}
+
+/// The lines hold the secrets of the store
+impl Drop for Document {
+    fn drop(&mut self) {
+        self.lines.zeroize();
+    }
+}
+
+/// Recovery codes are as secret as the key itself, so their values are hidden
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let attributes: Vec<(&str, &str)> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| match key.as_str() {
+                "recovery" | "recovery_used" => (key.as_str(), "***"),
+                _ => (key.as_str(), value.as_str()),
+            })
+            .collect();
+
+        f.debug_struct("Entry")
+            .field("name", &self.name)
+            .field("value", &self.value)
+            .field("attributes", &attributes)
+            .finish()
+    }
+}
+
+/// The lines hold the secrets of the store, so only their count is shown
+impl fmt::Debug for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Document")
+            .field("lines", &self.lines.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{}", self.line_ending)?;
+            }
+            write!(f, "{}", line)?;
+        }
+        if self.trailing_newline && !self.lines.is_empty() {
+            write!(f, "{}", self.line_ending)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses an entry line into its name and value, `None` for comments, blank
+/// lines and lines that aren't valid entries
+pub fn parse_entry(line: &str) -> Option<(String, String)> {
+    let line = line.trim_start();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (name, rest) = match line.strip_prefix('"') {
+        Some(quoted) => {
+            let (name, rest) = unquote(quoted)?;
+            (name, rest.trim_start().strip_prefix('=')?)
+        }
+        None => {
+            let (name, rest) = line.split_once('=')?;
+            (name.trim().to_string(), rest)
+        }
+    };
+
+    let rest = rest.trim();
+    let value = match rest.strip_prefix('"') {
+        Some(quoted) => {
+            let (value, rest) = unquote(quoted)?;
+            if !rest.trim().is_empty() {
+                return None;
+            }
+            value
+        }
+        None => rest.to_string(),
+    };
+
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((name, value))
+}
+
+/// Formats an entry line, quoting the name and value when needed
+pub fn format_entry(name: &str, value: &str) -> String {
+    format!("{} = {}", format_name(name), format_value(value))
+}
+
+pub fn format_name(name: &str) -> String {
+    if needs_quotes(name) || name.contains('=') {
+        quote(name)
+    } else {
+        name.to_string()
+    }
+}
+
+pub fn format_value(value: &str) -> String {
+    if needs_quotes(value) {
+        quote(value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Names end up on the command line and in listings, control characters in
+/// them are never intended
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        return Err(Error::Invalid("Key cannot be empty".to_string()));
+    }
+
+    if name.chars().any(char::is_control) {
+        return Err(Error::Invalid(
+            "Key name cannot contain control characters".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn needs_quotes(field: &str) -> bool {
+    field.is_empty()
+        || field.starts_with('#')
+        || field.starts_with('"')
+        || field.trim() != field
+        || field.chars().any(char::is_control)
+}
+
+fn quote(field: &str) -> String {
+    let mut quoted = String::with_capacity(field.len() + 2);
+    quoted.push('"');
+    for c in field.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Reads a quoted field up to its closing quote, `input` starts right after
+/// the opening one. Returns the field and whatever follows the closing quote.
+fn unquote(input: &str) -> Option<(String, &str)> {
+    let mut field = String::new();
+    let mut chars = input.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((field, &input[i + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => field.push('\n'),
+                'r' => field.push('\r'),
+                't' => field.push('\t'),
+                c => field.push(c),
+            },
+            c => field.push(c),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entry() {
+        assert_eq!(
+            parse_entry("github = JBSWY3DPEHPK3PXP"),
+            Some(("github".to_string(), "JBSWY3DPEHPK3PXP".to_string()))
+        );
+        assert_eq!(
+            parse_entry("  github=JBSWY3DPEHPK3PXP===  "),
+            Some(("github".to_string(), "JBSWY3DPEHPK3PXP===".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_entry_skips_comments_and_invalid_lines() {
+        assert_eq!(parse_entry("# github = JBSWY3DPEHPK3PXP"), None);
+        assert_eq!(parse_entry("   # indented comment"), None);
+        assert_eq!(parse_entry(""), None);
+        assert_eq!(parse_entry("no separator"), None);
+        assert_eq!(parse_entry(" = JBSWY3DPEHPK3PXP"), None);
+        assert_eq!(parse_entry("\"unterminated = JBSWY3DPEHPK3PXP"), None);
+        assert_eq!(parse_entry("name = \"value\" trailing"), None);
+    }
+
+    #[test]
+    fn test_format_entry_round_trip() {
+        let names = [
+            "github",
+            "work = prod",
+            "#hashtag",
+            "  padded  ",
+            "\"quoted\"",
+            "back\\slash",
+            "tab\there",
+        ];
+
+        for name in names {
+            let line = format_entry(name, "JBSWY3DPEHPK3PXP");
+            assert_eq!(
+                parse_entry(&line),
+                Some((name.to_string(), "JBSWY3DPEHPK3PXP".to_string())),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_format_entry_quotes_only_when_needed() {
+        assert_eq!(
+            format_entry("github", "JBSWY3DPEHPK3PXP===="),
+            "github = JBSWY3DPEHPK3PXP===="
+        );
+        assert_eq!(
+            format_entry("a=b", "JBSWY3DPEHPK3PXP"),
+            "\"a=b\" = JBSWY3DPEHPK3PXP"
+        );
+        assert_eq!(format_entry("github", " padded"), "github = \" padded\"");
+    }
+
+    #[test]
+    fn test_document_round_trip() {
+        let contents = [
+            "",
+            "# comment only",
+            "# header\n\ngithub = JBSWY3DPEHPK3PXP\n",
+            "github = JBSWY3DPEHPK3PXP\n\n\n# trailing comment",
+            "# windows\r\ngithub = JBSWY3DPEHPK3PXP\r\n",
+            "  not an entry\ngithub = JBSWY3DPEHPK3PXP",
+        ];
+
+        for content in contents {
+            assert_eq!(Document::parse(content).to_string(), content);
+        }
+
+        let debug = format!("{:?}", Document::parse(contents[2]));
+        assert!(!debug.contains("JBSWY3DPEHPK3PXP"));
+
+        let document = Document::parse(
+            "github = JBSWY3DPEHPK3PXP\n    note = work\n    recovery = aaaa-1111\n    \
+             recovery_used = bbbb-2222\n",
+        );
+        let debug = format!("{:?}", document.entry("github").unwrap());
+        assert!(debug.contains("work"));
+        assert!(!debug.contains("JBSWY3DPEHPK3PXP"));
+        assert!(!debug.contains("aaaa-1111") && !debug.contains("bbbb-2222"));
+    }
+
+    #[test]
+    fn test_document_find() {
+        let document =
+            Document::parse("# git = comment\ngithub = JBSWY3DPEHPK3PXP\ngit = JBSWY3DPEHPK3PXP\n");
+
+        assert_eq!(document.find("git"), Some(2));
+        assert_eq!(document.find("github"), Some(1));
+        assert_eq!(document.find("gitlab"), None);
+    }
+
+    #[test]
+    fn test_document_push() {
+        let mut document = Document::parse("# header");
+        document.push("github = JBSWY3DPEHPK3PXP".to_string());
+        assert_eq!(
+            document.to_string(),
+            "# header\ngithub = JBSWY3DPEHPK3PXP\n"
+        );
+
+        let mut document = Document::parse("# header\r\n");
+        document.push("github = JBSWY3DPEHPK3PXP".to_string());
+        assert_eq!(
+            document.to_string(),
+            "# header\r\ngithub = JBSWY3DPEHPK3PXP\r\n"
+        );
+    }
+
+    #[test]
+    fn test_document_entries_with_attributes() {
+        let document = Document::parse(
+            "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\n    tags = work, dev\n\ttags = oss\n  indented = GEZDGNBVGY3TQOJQGEZDGNBVGY\n# comment\n    tags = GEZDGNBVGY3TQOJQGEZDGNBVGY\nwork/aws/prod = GEZDGNBVGY3TQOJQGEZDGNBVGY\n",
+        );
+
+        let entries = document.entries();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["github", "indented", "tags", "work/aws/prod"]);
+
+        // Indented lines that aren't attributes stay entries of their own
+        let github = document.entry("github").unwrap();
+        assert_eq!(github.tags(), vec!["work", "dev", "oss"]);
+        assert!(github.attribute("indented").is_empty());
+        assert_eq!(document.entry_range("github"), Some(0..3));
+        assert_eq!(
+            document.entry("indented").unwrap().value.expose(),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY"
+        );
+        assert_eq!(document.entry_range("indented"), Some(3..4));
+        assert_eq!(document.find("tags"), Some(5));
+
+        let prod = document.entry("work/aws/prod").unwrap();
+        assert_eq!(prod.folder(), Some("work/aws"));
+        assert!(prod.in_folder("work"));
+        assert!(prod.in_folder("work/aws/"));
+        assert!(!prod.in_folder("wor"));
+        assert!(!github.in_folder("work"));
+    }
+
+    #[test]
+    fn test_document_set_attribute() {
+        let mut document = Document::parse(
+            "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\n    note = keep me\n    tags = old\ngitlab = GEZDGNBVGY3TQOJQGEZDGNBVGY\n",
+        );
+
+        assert!(document.set_attribute("github", "tags", &["work".to_string(), "dev".to_string()]));
+        assert_eq!(
+            document.to_string(),
+            "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\n    note = keep me\n    tags = work\n    tags = dev\ngitlab = GEZDGNBVGY3TQOJQGEZDGNBVGY\n"
+        );
+
+        assert!(document.set_attribute("github", "tags", &[]));
+        assert_eq!(
+            document.entry("github").unwrap().tags(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            document.entry("github").unwrap().attribute("note"),
+            vec!["keep me"]
+        );
+        assert!(!document.set_attribute("missing", "tags", &[]));
+    }
+
+    #[test]
+    fn test_document_trash() {
+        let mut document = Document::parse(
+            "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\n    deleted = 100\n    tags = work\ngithub = MFRGGZDFMZTWQ2LKNNWG23TPOA\ngitlab = GEZDGNBVGY3TQOJQGEZDGNBVGY\n    deleted = 200\n",
+        );
+
+        assert_eq!(document.entries().len(), 1);
+        assert_eq!(
+            document.entry("github").unwrap().value.expose(),
+            "MFRGGZDFMZTWQ2LKNNWG23TPOA"
+        );
+        assert_eq!(document.find("github"), Some(3));
+        assert_eq!(document.find("gitlab"), None);
+
+        let trashed = document.trashed();
+        assert_eq!(trashed.len(), 2);
+        assert_eq!(trashed[0].1.name, "gitlab");
+        assert_eq!(trashed[1].1.deleted(), Some(100));
+        assert_eq!(trashed[1].0, 0..3);
+
+        document.untrash(trashed[0].0.clone());
+        assert_eq!(document.find("gitlab"), Some(4));
+        assert_eq!(
+            document.to_string(),
+            "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\n    deleted = 100\n    tags = work\ngithub = MFRGGZDFMZTWQ2LKNNWG23TPOA\ngitlab = GEZDGNBVGY3TQOJQGEZDGNBVGY\n"
+        );
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("github").is_ok());
+        assert!(validate_name("work = prod").is_ok());
+        assert!(validate_name("   ").is_err());
+        assert!(validate_name("new\nline").is_err());
+    }
+}
@@ -1,8 +1,29 @@
+#[cfg(unix)]
+mod agent;
 mod app;
+mod atomic;
+mod audit;
+mod backup;
 mod clip;
+mod config;
+mod error;
+mod format;
+mod lock;
+mod menu;
+mod ntp;
+mod otp;
+mod permissions;
+mod secret;
 mod store;
 mod utils;
 
 pub use app::App;
-pub use clip::Clip;
+pub use audit::{AuditLog, Event};
+pub use clip::{Clip, ClipboardProvider};
+pub use config::Config;
+pub use error::{Error, Result};
+pub use format::Entry;
+pub use lock::StoreLock;
+pub use menu::{Launcher, Menu};
+pub use otp::{Algorithm, Clock, FixedClock, OffsetClock, Otp, OtpType, OtpUri, SystemClock};
 pub use store::Store;
@@ -0,0 +1,145 @@
+use crate::error::{Context, Error, Result};
+use crate::permissions;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Advisory lock on a store, held from loading the store until the last write
+/// so concurrent ova processes don't overwrite each other's changes. The lock
+/// is released when the value is dropped.
+pub struct StoreLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl StoreLock {
+    pub fn acquire(store_path: &Path, timeout: Duration) -> Result<Self> {
+        let path = Self::lock_path(store_path);
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            permissions::create_private_dir(dir)?;
+        }
+
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(permissions::PRIVATE_FILE_MODE);
+        }
+
+        let mut file = options
+            .open(&path)
+            .with_context(|| format!("Error opening lock file at - {}", path.to_string_lossy()))?;
+
+        let started = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => break,
+                Err(TryLockError::WouldBlock) if started.elapsed() < timeout => {
+                    thread::sleep(RETRY_INTERVAL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(Error::Locked(Self::holder(&path)));
+                }
+                Err(TryLockError::Error(err)) => {
+                    return Err(err).with_context(|| {
+                        format!("Error locking store at - {}", store_path.to_string_lossy())
+                    });
+                }
+            }
+        }
+
+        file.set_len(0)
+            .and_then(|_| file.write_all(process::id().to_string().as_bytes()))
+            .and_then(|_| file.flush())
+            .with_context(|| format!("Error writing lock file at - {}", path.to_string_lossy()))?;
+
+        Ok(StoreLock { file, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn lock_path(store_path: &Path) -> PathBuf {
+        let mut file_name = store_path
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_default();
+        file_name.push(".lock");
+
+        store_path.with_file_name(file_name)
+    }
+
+    fn holder(path: &Path) -> Option<u32> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        // The lock file itself stays, removing it would race with a process
+        // that already opened it and is waiting for the lock
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::remove_file;
+
+    #[test]
+    fn test_lock_path() {
+        let path = StoreLock::lock_path(Path::new("/home/ova/.config/ova.store"));
+        assert_eq!(path, PathBuf::from("/home/ova/.config/ova.store.lock"));
+    }
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let store_path = PathBuf::new().join("test_lock_exclusive.store");
+
+        let lock = StoreLock::acquire(&store_path, DEFAULT_LOCK_TIMEOUT).unwrap();
+        let result = StoreLock::acquire(&store_path, Duration::from_millis(100));
+
+        let err = result.err().unwrap().to_string();
+        assert_eq!(err, format!("Store is locked by pid {}", process::id()));
+
+        let lock_path = lock.path().to_path_buf();
+        drop(lock);
+        let lock = StoreLock::acquire(&store_path, Duration::from_millis(100));
+        assert!(lock.is_ok());
+
+        drop(lock);
+        remove_file(lock_path).unwrap();
+    }
+
+    #[test]
+    fn test_lock_waits_for_release() {
+        let store_path = PathBuf::new().join("test_lock_wait.store");
+
+        let lock = StoreLock::acquire(&store_path, DEFAULT_LOCK_TIMEOUT).unwrap();
+        let lock_path = lock.path().to_path_buf();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(150));
+            drop(lock);
+        });
+
+        let lock = StoreLock::acquire(&store_path, DEFAULT_LOCK_TIMEOUT);
+        assert!(lock.is_ok());
+
+        handle.join().unwrap();
+        drop(lock);
+        remove_file(lock_path).unwrap();
+    }
+}
@@ -1,8 +1,49 @@
-use anyhow::Result;
-use ova::App;
+use ova::{App, Error};
+use std::process;
 
-fn main() -> Result<()> {
+/// Exit code of errors without a more specific one. Invalid arguments exit
+/// with 2, that one comes from clap.
+const EXIT_FAILURE: i32 = 1;
+/// The requested keys are not in the store
+const EXIT_NOT_FOUND: i32 = 3;
+/// A key that was about to be created already exists
+const EXIT_EXISTS: i32 = 4;
+/// The store file can't be read as a store
+const EXIT_CORRUPT_STORE: i32 = 5;
+/// Another process holds the store lock
+const EXIT_LOCKED: i32 = 6;
+/// The store or its directory belongs to another user
+const EXIT_OWNERSHIP: i32 = 7;
+/// The key isn't valid or no code could be generated from it
+const EXIT_OTP: i32 = 8;
+/// Copying or typing the code failed
+const EXIT_CLIPBOARD: i32 = 9;
+/// Invalid config file or value
+const EXIT_CONFIG: i32 = 10;
+/// The code given to verify doesn't match
+const EXIT_CODE_MISMATCH: i32 = 11;
+
+fn exit_code(err: &Error) -> i32 {
+    match err {
+        Error::KeyNotFound(_) => EXIT_NOT_FOUND,
+        Error::KeyExists(_) => EXIT_EXISTS,
+        Error::CorruptStore { .. } => EXIT_CORRUPT_STORE,
+        Error::Locked(_) => EXIT_LOCKED,
+        Error::Ownership { .. } => EXIT_OWNERSHIP,
+        Error::Otp(_) => EXIT_OTP,
+        Error::Clipboard(_) => EXIT_CLIPBOARD,
+        Error::Config(_) => EXIT_CONFIG,
+        Error::CodeMismatch { .. } => EXIT_CODE_MISMATCH,
+        _ => EXIT_FAILURE,
+    }
+}
+
+fn main() {
     let app = App::new();
-    app.run()?;
-    Ok(())
+
+    if let Err(err) = app.run() {
+        let code = exit_code(&err);
+        eprintln!("Error: {:?}", anyhow::Error::new(err));
+        process::exit(code);
+    }
 }
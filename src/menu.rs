@@ -0,0 +1,120 @@
+use crate::error::{Context, Error, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Launcher {
+    Rofi,
+    Dmenu,
+    Fuzzel,
+    Custom,
+}
+
+impl Launcher {
+    pub fn default_command(&self) -> Option<&'static str> {
+        match self {
+            Launcher::Rofi => Some("rofi -dmenu -i -p ova"),
+            Launcher::Dmenu => Some("dmenu -i -p ova"),
+            Launcher::Fuzzel => Some("fuzzel --dmenu --prompt 'ova> '"),
+            Launcher::Custom => None,
+        }
+    }
+}
+
+pub struct Menu {
+    pub command: String,
+}
+
+impl Menu {
+    pub fn new(launcher: Launcher, command: Option<&str>) -> Result<Self> {
+        let command = match (command, launcher.default_command()) {
+            (Some(command), _) => command,
+            (None, Some(command)) => command,
+            (None, None) => {
+                return Err(Error::Menu(
+                    "Custom launcher requires a --command to run".to_string(),
+                ));
+            }
+        };
+
+        if command.trim().is_empty() {
+            return Err(Error::Menu("Launcher command cannot be empty".to_string()));
+        }
+
+        Ok(Menu {
+            command: command.to_string(),
+        })
+    }
+
+    /// Pipes `names` one per line into the launcher and returns the picked one
+    pub fn select(&self, names: &[&String]) -> Result<String> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::Menu(format!("Failed to execute launcher: {}", e)))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            let input = names
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            stdin
+                .write_all(input.as_bytes())
+                .and_then(|_| stdin.write_all(b"\n"))
+                .with_context(|| "Error writing to the launcher")?;
+        }
+
+        let output = child
+            .wait_with_output()
+            .with_context(|| "Error reading from the launcher")?;
+        let selection = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+        if !output.status.success() || selection.is_empty() {
+            return Err(Error::Menu("No entry selected".to_string()));
+        }
+
+        Ok(selection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_menu_custom_without_command() {
+        let menu = Menu::new(Launcher::Custom, None);
+        assert!(menu.is_err());
+    }
+
+    #[test]
+    fn test_menu_command_override() {
+        let menu = Menu::new(Launcher::Rofi, Some("my-rofi -dmenu")).unwrap();
+        assert_eq!(menu.command, "my-rofi -dmenu");
+    }
+
+    #[test]
+    fn test_menu_select() {
+        let first = "github".to_string();
+        let second = "gitlab".to_string();
+        let menu = Menu::new(Launcher::Custom, Some("tail -n 1")).unwrap();
+
+        let selection = menu.select(&[&first, &second]).unwrap();
+        assert_eq!(selection, "gitlab");
+    }
+
+    #[test]
+    fn test_menu_select_cancelled() {
+        let name = "github".to_string();
+        let menu = Menu::new(Launcher::Custom, Some("cat >/dev/null; exit 1")).unwrap();
+
+        assert!(menu.select(&[&name]).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! Minimal SNTP client (RFC 4330) to find out how far the system clock is off.
+//! Codes are only accepted within a time step or so of the server's clock,
+//! a skewed system clock makes them fail.
+
+use crate::error::{Context, Error, Result};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const NTP_PORT: u16 = 123;
+
+pub const DEFAULT_NTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Seconds from the start of the NTP era, 1900, to the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+const PACKET_LEN: usize = 48;
+
+const VERSION: u8 = 4;
+
+const MODE_CLIENT: u8 = 3;
+
+const MODE_SERVER: u8 = 4;
+
+/// How far the system clock is off compared to a time server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockOffset {
+    /// Seconds to add to the system time to get the server's time
+    pub offset: f64,
+    /// Round trip time to the server in seconds
+    pub delay: f64,
+}
+
+impl ClockOffset {
+    /// The offset rounded to whole seconds, as used for `time_offset`
+    pub fn seconds(&self) -> i64 {
+        self.offset.round() as i64
+    }
+}
+
+/// Asks `server`, a host name or address with an optional port, for the time
+/// and compares it with the system clock
+pub fn query(server: &str, timeout: Duration) -> Result<ClockOffset> {
+    let address = resolve(server)?;
+    let local = if address.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+
+    let socket = UdpSocket::bind(local)
+        .and_then(|socket| {
+            socket.set_read_timeout(Some(timeout))?;
+            socket.connect(address)?;
+            Ok(socket)
+        })
+        .with_context(|| format!("Error opening a socket to - {}", server))?;
+
+    let mut request = [0u8; PACKET_LEN];
+    request[0] = VERSION << 3 | MODE_CLIENT;
+    let sent = now();
+    request[40..48].copy_from_slice(&sent.to_be_bytes());
+    socket
+        .send(&request)
+        .with_context(|| format!("Error sending a request to - {}", server))?;
+
+    let mut response = [0u8; 2 * PACKET_LEN];
+    let len = socket
+        .recv(&mut response)
+        .with_context(|| format!("No answer from - {}", server))?;
+    let received = now();
+
+    parse_response(&response[..len], sent, received)
+}
+
+fn resolve(server: &str) -> Result<SocketAddr> {
+    let addresses = match server.to_socket_addrs() {
+        Ok(addresses) => addresses,
+        Err(_) => (server, NTP_PORT)
+            .to_socket_addrs()
+            .with_context(|| format!("Error resolving - {}", server))?,
+    };
+
+    addresses
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::Ntp(format!("No address found for {}", server)))
+}
+
+/// Works out the offset from a server response, `sent` and `received` being
+/// the NTP timestamps of the request leaving and the response arriving
+fn parse_response(response: &[u8], sent: u64, received: u64) -> Result<ClockOffset> {
+    if response.len() < PACKET_LEN {
+        return Err(Error::Ntp(
+            "Response from the time server is too short".to_string(),
+        ));
+    }
+
+    let timestamp = |at: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&response[at..at + 8]);
+        u64::from_be_bytes(bytes)
+    };
+
+    let leap = response[0] >> 6;
+    let mode = response[0] & 0b111;
+    let stratum = response[1];
+
+    if mode != MODE_SERVER {
+        return Err(Error::Ntp(format!(
+            "Time server answered in mode {} instead of {}",
+            mode, MODE_SERVER
+        )));
+    }
+
+    // Kiss-o'-Death, the reason is an ASCII code in the reference id
+    if stratum == 0 {
+        return Err(Error::Ntp(format!(
+            "Time server refused the request ({})",
+            String::from_utf8_lossy(&response[12..16]).trim_end_matches('\0')
+        )));
+    }
+
+    if leap == 3 {
+        return Err(Error::Ntp("Time server is not synchronized".to_string()));
+    }
+
+    if timestamp(24) != sent {
+        return Err(Error::Ntp(
+            "Time server answered a different request".to_string(),
+        ));
+    }
+
+    let server_received = timestamp(32);
+    let server_sent = timestamp(40);
+    if server_sent == 0 {
+        return Err(Error::Ntp("Time server sent no time".to_string()));
+    }
+
+    // Differences of the 32.32 fixed point timestamps are right across era
+    // boundaries as long as the clocks are less than 68 years apart
+    let diff = |a: u64, b: u64| a.wrapping_sub(b) as i64 as f64 / (1u64 << 32) as f64;
+    let offset = (diff(server_received, sent) + diff(server_sent, received)) / 2.0;
+    let delay = diff(received, sent) - diff(server_sent, server_received);
+
+    Ok(ClockOffset { offset, delay })
+}
+
+/// Current system time as an NTP timestamp
+fn now() -> u64 {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = (time.as_secs() + NTP_UNIX_OFFSET) as u32 as u64;
+    let fraction = (u64::from(time.subsec_nanos()) << 32) / 1_000_000_000;
+
+    seconds << 32 | fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn response(originate: u64, receive: u64, transmit: u64) -> [u8; PACKET_LEN] {
+        let mut response = [0u8; PACKET_LEN];
+        response[0] = VERSION << 3 | MODE_SERVER;
+        response[1] = 2;
+        response[24..32].copy_from_slice(&originate.to_be_bytes());
+        response[32..40].copy_from_slice(&receive.to_be_bytes());
+        response[40..48].copy_from_slice(&transmit.to_be_bytes());
+        response
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let second = 1u64 << 32;
+        let sent = 3_900_000_000 * second;
+
+        // Server is 10 seconds ahead, one second each way and one second
+        // spent on the server
+        let answer = response(sent, sent + 11 * second, sent + 12 * second);
+        let offset = parse_response(&answer, sent, sent + 3 * second).unwrap();
+        assert_eq!(offset.offset, 10.0);
+        assert_eq!(offset.delay, 2.0);
+        assert_eq!(offset.seconds(), 10);
+
+        // Server is behind, across the end of the NTP era
+        let sent = u64::MAX - second + 1;
+        let answer = response(
+            sent,
+            sent.wrapping_sub(99 * second),
+            sent.wrapping_sub(99 * second),
+        );
+        let offset = parse_response(&answer, sent, sent.wrapping_add(2 * second)).unwrap();
+        assert_eq!(offset.seconds(), -100);
+
+        assert!(parse_response(&answer, sent + 1, sent.wrapping_add(2 * second)).is_err());
+        assert!(parse_response(&answer[..40], sent, sent).is_err());
+
+        let mut kiss = response(sent, 0, 0);
+        kiss[1] = 0;
+        kiss[12..16].copy_from_slice(b"RATE");
+        let err = parse_response(&kiss, sent, sent).unwrap_err();
+        assert_eq!(err.to_string(), "Time server refused the request (RATE)");
+    }
+
+    #[test]
+    fn test_query_local_server() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+
+        // Stand-in for an NTP server whose clock is 100 seconds ahead
+        let handle = thread::spawn(move || {
+            let mut request = [0u8; PACKET_LEN];
+            let (_, client) = server.recv_from(&mut request).unwrap();
+            assert_eq!(request[0] & 0b111, MODE_CLIENT);
+
+            let mut originate = [0u8; 8];
+            originate.copy_from_slice(&request[40..48]);
+            let time = now().wrapping_add(100 << 32);
+            let answer = response(u64::from_be_bytes(originate), time, time);
+            server.send_to(&answer, client).unwrap();
+        });
+
+        let offset = query(&address, DEFAULT_NTP_TIMEOUT).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(offset.seconds(), 100);
+        assert!(offset.delay >= 0.0 && offset.delay < 1.0);
+    }
+}
@@ -0,0 +1,529 @@
+//! HOTP (RFC 4226) and TOTP (RFC 6238) codes. Codes are generated for an
+//! explicit counter or point in time, the current time comes from a [`Clock`]
+//! so it can be replaced, e.g. by `get --at` or in tests.
+//!
+//! Steam Guard codes are TOTP as well, with the HMAC result written as five
+//! characters of [`STEAM_ALPHABET`] instead of decimal digits.
+
+use crate::error::{Error, Result};
+use crate::secret::{decode_secret, normalize_secret, Secret};
+use clap::ValueEnum;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use totp_rs::TOTP;
+use zeroize::Zeroizing;
+
+/// Seconds one TOTP code is valid for, used by nearly every service
+pub const DEFAULT_STEP: u64 = 30;
+
+/// Most time steps before and after the current one `verify` looks at. Each
+/// step accepted makes guessing a code easier.
+pub const MAX_WINDOW: u64 = 10;
+
+/// Characters Steam Guard codes are made of
+pub const STEAM_ALPHABET: &str = "23456789BCDFGHJKMNPQRTVWXY";
+
+/// Length of a Steam Guard code
+pub const STEAM_CODE_LEN: usize = 5;
+
+const OTPAUTH_SCHEME: &str = "otpauth://";
+
+/// Source of the current time in seconds since the Unix epoch
+pub trait Clock {
+    fn now(&self) -> Result<u64>;
+}
+
+/// The system time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Result<u64> {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .map_err(|_| Error::Otp("System time is before the Unix epoch".to_string()))
+    }
+}
+
+/// Always the same point in time
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> Result<u64> {
+        Ok(self.0)
+    }
+}
+
+/// `clock` shifted by `offset` seconds, making up for a system clock that is
+/// off
+pub struct OffsetClock<C: Clock> {
+    pub clock: C,
+    pub offset: i64,
+}
+
+impl<C: Clock> Clock for OffsetClock<C> {
+    fn now(&self) -> Result<u64> {
+        self.clock
+            .now()?
+            .checked_add_signed(self.offset)
+            .ok_or_else(|| Error::Otp("Time offset is out of range".to_string()))
+    }
+}
+
+/// Kind of codes an entry generates, kept in its `type` attribute
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OtpType {
+    /// Numeric codes as shown by authenticator apps
+    #[default]
+    Totp,
+    /// Steam Guard codes of five letters and digits
+    Steam,
+}
+
+impl OtpType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OtpType::Totp => "totp",
+            OtpType::Steam => "steam",
+        }
+    }
+
+    /// Whether `code` looks like a code of this type, without checking it
+    /// against a key
+    pub fn is_well_formed(&self, code: &str, digits: usize) -> bool {
+        match self {
+            OtpType::Totp => code.len() == digits && code.chars().all(|c| c.is_ascii_digit()),
+            OtpType::Steam => {
+                code.len() == STEAM_CODE_LEN && code.chars().all(|c| STEAM_ALPHABET.contains(c))
+            }
+        }
+    }
+}
+
+impl fmt::Display for OtpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OtpType {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "totp" => Ok(OtpType::Totp),
+            "steam" => Ok(OtpType::Steam),
+            _ => Err(Error::Otp(format!("Unknown entry type '{}'", value))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// Generates codes for one secret. The secret is wiped from memory when the
+/// value is dropped.
+pub struct Otp {
+    // A step of one second makes totp-rs use the time as the HOTP counter
+    hotp: TOTP,
+    step: u64,
+}
+
+impl Otp {
+    /// TOTP for a Base32 key from the store, SHA1 with 30 second steps as
+    /// authenticator apps do
+    pub fn new(key: &Secret, digits: usize) -> Result<Self> {
+        Self::from_bytes(&key_bytes(key)?, Algorithm::Sha1, digits, DEFAULT_STEP)
+    }
+
+    /// Steam Guard codes for a Base32 key from the store
+    pub fn steam(key: &Secret) -> Result<Self> {
+        let secret = key_bytes(key)?;
+
+        // totp-rs only takes 6 to 8 digits, which doesn't apply to Steam
+        let hotp = TOTP::new_unchecked(
+            totp_rs::Algorithm::Steam,
+            STEAM_CODE_LEN,
+            0,
+            1,
+            secret.to_vec(),
+        );
+
+        Ok(Otp {
+            hotp,
+            step: DEFAULT_STEP,
+        })
+    }
+
+    /// Codes of `otp_type` for a Base32 key from the store, `digits` only
+    /// applies to TOTP
+    pub fn for_type(key: &Secret, otp_type: OtpType, digits: usize) -> Result<Self> {
+        match otp_type {
+            OtpType::Totp => Self::new(key, digits),
+            OtpType::Steam => Self::steam(key),
+        }
+    }
+
+    /// Codes of `digits` digits for the raw `secret`, `step` seconds apart
+    pub fn from_bytes(
+        secret: &[u8],
+        algorithm: Algorithm,
+        digits: usize,
+        step: u64,
+    ) -> Result<Self> {
+        if step == 0 {
+            return Err(Error::Otp(
+                "Time step must be at least 1 second".to_string(),
+            ));
+        }
+
+        let algorithm = match algorithm {
+            Algorithm::Sha1 => totp_rs::Algorithm::SHA1,
+            Algorithm::Sha256 => totp_rs::Algorithm::SHA256,
+            Algorithm::Sha512 => totp_rs::Algorithm::SHA512,
+        };
+        let hotp = TOTP::new(algorithm, digits, 0, 1, secret.to_vec())
+            .map_err(|err| Error::Otp(err.to_string()))?;
+
+        Ok(Otp { hotp, step })
+    }
+
+    /// HOTP code for `counter`
+    pub fn hotp(&self, counter: u64) -> String {
+        self.hotp.generate(counter)
+    }
+
+    /// TOTP code valid at `time` seconds since the Unix epoch
+    pub fn totp_at(&self, time: u64) -> String {
+        self.hotp(time / self.step)
+    }
+
+    /// TOTP code valid at the time `clock` reports
+    pub fn totp(&self, clock: &dyn Clock) -> Result<String> {
+        Ok(self.totp_at(clock.now()?))
+    }
+
+    /// Checks `code` against the TOTP codes up to `window` time steps before
+    /// and after `time`. Returns the offset in time steps of the closest
+    /// match, negative when the code is from an earlier step. Windows beyond
+    /// [`MAX_WINDOW`] are cut down to it.
+    pub fn verify_at(&self, code: &str, time: u64, window: u64) -> Option<i64> {
+        let current = time / self.step;
+
+        for distance in 0..=window.min(MAX_WINDOW) {
+            let offsets = if distance == 0 {
+                vec![0]
+            } else {
+                vec![-(distance as i64), distance as i64]
+            };
+
+            for offset in offsets {
+                let counter = match current.checked_add_signed(offset) {
+                    Some(counter) => counter,
+                    None => continue,
+                };
+                if constant_time_eq(self.hotp(counter).as_bytes(), code.as_bytes()) {
+                    return Some(offset);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// [`Otp::verify_at`] the time `clock` reports
+    pub fn verify(&self, code: &str, clock: &dyn Clock, window: u64) -> Result<Option<i64>> {
+        Ok(self.verify_at(code, clock.now()?, window))
+    }
+}
+
+/// Key and code type read from an `otpauth://` URI, as in the QR codes
+/// services show. `otpauth://steam/` and `encoder=steam` mark Steam keys.
+#[derive(Debug)]
+pub struct OtpUri {
+    pub secret: Secret,
+    pub otp_type: OtpType,
+    /// Digits the URI asks for, kept with the key
+    pub digits: Option<usize>,
+}
+
+impl OtpUri {
+    pub fn is_uri(value: &str) -> bool {
+        value
+            .get(..OTPAUTH_SCHEME.len())
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case(OTPAUTH_SCHEME))
+    }
+
+    pub fn parse(uri: &str) -> Result<Self> {
+        if !Self::is_uri(uri) {
+            return Err(Error::Otp(format!(
+                "Key URI must start with {}",
+                OTPAUTH_SCHEME
+            )));
+        }
+
+        let rest = &uri[OTPAUTH_SCHEME.len()..];
+        let (kind, rest) = rest.split_once('/').unwrap_or((rest, ""));
+        let query = rest.split_once('?').map_or("", |(_, query)| query);
+
+        let mut otp_type = match kind.to_ascii_lowercase().as_str() {
+            "totp" => OtpType::Totp,
+            "steam" => OtpType::Steam,
+            "hotp" => {
+                return Err(Error::Otp(
+                    "Counter based (HOTP) keys are not supported".to_string(),
+                ))
+            }
+            _ => return Err(Error::Otp(format!("Unknown key URI type '{}'", kind))),
+        };
+
+        let mut secret = None;
+        let mut digits = None;
+        let mut algorithm = None;
+        let mut period = None;
+
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let value = percent_decode(value)?;
+
+            match name.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(normalize_secret(&value)?),
+                "digits" => {
+                    digits = Some(value.parse().map_err(|_| {
+                        Error::Otp(format!("Invalid digits '{}' in key URI", value.as_str()))
+                    })?)
+                }
+                "algorithm" => algorithm = Some(value.to_ascii_uppercase()),
+                "period" => period = Some(value.to_string()),
+                "encoder" if value.eq_ignore_ascii_case("steam") => otp_type = OtpType::Steam,
+                _ => {}
+            }
+        }
+
+        // Steam always uses SHA1 and 30 second steps, whatever the URI says
+        if otp_type == OtpType::Totp {
+            if algorithm
+                .as_deref()
+                .is_some_and(|algorithm| algorithm != "SHA1")
+            {
+                return Err(Error::Otp("Only SHA1 keys are supported".to_string()));
+            }
+            if period.is_some_and(|period| period != DEFAULT_STEP.to_string()) {
+                return Err(Error::Otp(format!(
+                    "Only keys with {} second steps are supported",
+                    DEFAULT_STEP
+                )));
+            }
+        }
+
+        Ok(OtpUri {
+            secret: secret.ok_or_else(|| Error::Otp("Key URI has no secret".to_string()))?,
+            otp_type,
+            digits: digits.filter(|_| otp_type == OtpType::Totp),
+        })
+    }
+}
+
+/// Decodes `%XX` escapes and `+` of a URI query value
+fn percent_decode(value: &str) -> Result<Zeroizing<String>> {
+    let bytes = value.as_bytes();
+    let mut decoded = Zeroizing::new(Vec::with_capacity(bytes.len()));
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let byte = value
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| Error::Otp("Invalid escape in key URI".to_string()))?;
+                decoded.push(byte);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded.to_vec())
+        .map(Zeroizing::new)
+        .map_err(|_| Error::Otp("Invalid escape in key URI".to_string()))
+}
+
+fn key_bytes(key: &Secret) -> Result<Zeroizing<Vec<u8>>> {
+    if key.expose().trim().is_empty() {
+        return Err(Error::Otp("Key cannot be empty".to_string()));
+    }
+
+    decode_secret(normalize_secret(key.expose())?.expose())
+}
+
+/// Compares without returning early, so the time taken doesn't tell how many
+/// leading digits of a guessed code were right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED_SHA1: &[u8] = b"12345678901234567890";
+    const SEED_SHA256: &[u8] = b"12345678901234567890123456789012";
+    const SEED_SHA512: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    #[test]
+    fn test_rfc4226_vectors() {
+        let otp = Otp::from_bytes(SEED_SHA1, Algorithm::Sha1, 6, DEFAULT_STEP).unwrap();
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(otp.hotp(counter as u64), *code);
+        }
+    }
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        let vectors: [(u64, &str, &str, &str); 6] = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        let sha1 = Otp::from_bytes(SEED_SHA1, Algorithm::Sha1, 8, DEFAULT_STEP).unwrap();
+        let sha256 = Otp::from_bytes(SEED_SHA256, Algorithm::Sha256, 8, DEFAULT_STEP).unwrap();
+        let sha512 = Otp::from_bytes(SEED_SHA512, Algorithm::Sha512, 8, DEFAULT_STEP).unwrap();
+
+        for (time, code_sha1, code_sha256, code_sha512) in vectors {
+            assert_eq!(sha1.totp_at(time), code_sha1);
+            assert_eq!(sha256.totp_at(time), code_sha256);
+            assert_eq!(sha512.totp(&FixedClock(time)).unwrap(), code_sha512);
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let otp = Otp::from_bytes(SEED_SHA1, Algorithm::Sha1, 8, DEFAULT_STEP).unwrap();
+
+        assert_eq!(otp.verify_at("89005924", 1234567890, 0), Some(0));
+        // 1111111109 and 1111111111 fall into neighbouring time steps
+        assert_eq!(otp.verify_at("14050471", 1111111109, 1), Some(1));
+        assert_eq!(otp.verify_at("07081804", 1111111111, 1), Some(-1));
+        assert_eq!(otp.verify_at("07081804", 1111111111, 0), None);
+        assert_eq!(otp.verify_at("0708180", 1111111111, 1), None);
+        assert_eq!(otp.verify_at("94287082", 59, 2), Some(0));
+        assert_eq!(otp.verify_at("94287082", 59, u64::MAX), Some(0));
+        assert_eq!(
+            otp.verify_at("89005924", 1234567890 + 11 * 30, u64::MAX),
+            None
+        );
+        assert_eq!(
+            otp.verify("89005924", &FixedClock(1234567890 + 90), 3)
+                .unwrap(),
+            Some(-3)
+        );
+    }
+
+    #[test]
+    fn test_offset_clock() {
+        let clock = OffsetClock {
+            clock: FixedClock(1000),
+            offset: -30,
+        };
+        assert_eq!(clock.now().unwrap(), 970);
+
+        let clock = OffsetClock {
+            clock: FixedClock(10),
+            offset: -30,
+        };
+        assert!(clock.now().is_err());
+    }
+
+    #[test]
+    fn test_new_from_store_key() {
+        // Base32 of the RFC 6238 SHA1 seed
+        let key = Secret::from("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        let otp = Otp::new(&key, 8).unwrap();
+
+        assert_eq!(otp.totp_at(1111111109), "07081804");
+        assert!(Otp::new(&Secret::from(" "), 6).is_err());
+        assert!(Otp::from_bytes(SEED_SHA1, Algorithm::Sha1, 6, 0).is_err());
+    }
+
+    #[test]
+    fn test_steam() {
+        let key = Secret::from("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        let otp = Otp::for_type(&key, OtpType::Steam, 8).unwrap();
+
+        assert_eq!(otp.totp_at(59), "PV9M4");
+        assert_eq!(otp.totp_at(1111111109), "PY4YB");
+        assert_eq!(otp.totp_at(1234567890), "VHHQY");
+        assert_eq!(otp.totp_at(2000000000), "9N776");
+        assert_eq!(otp.verify_at("VHHQY", 1234567890 + 30, 1), Some(-1));
+        assert!(Otp::steam(&Secret::from("")).is_err());
+    }
+
+    #[test]
+    fn test_otp_uri() {
+        let uri = OtpUri::parse(
+            "otpauth://steam/Steam:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Steam",
+        )
+        .unwrap();
+        assert_eq!(uri.otp_type, OtpType::Steam);
+        assert_eq!(uri.secret.expose(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(uri.digits, None);
+
+        let uri = OtpUri::parse(
+            "OTPAUTH://totp/Steam%3Aalice?secret=gezdgnbvgy3tqojq%20gezdgnbvgy3tqojq&encoder=steam&digits=5",
+        )
+        .unwrap();
+        assert_eq!(uri.otp_type, OtpType::Steam);
+        assert_eq!(uri.secret.expose(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+
+        let uri = OtpUri::parse(
+            "otpauth://totp/GitHub:alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&digits=8&period=30",
+        )
+        .unwrap();
+        assert_eq!(uri.otp_type, OtpType::Totp);
+        assert_eq!(uri.digits, Some(8));
+
+        assert!(OtpUri::parse("otpauth://hotp/x?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").is_err());
+        assert!(OtpUri::parse("otpauth://totp/x?issuer=GitHub").is_err());
+        assert!(OtpUri::parse(
+            "otpauth://totp/x?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&algorithm=SHA256"
+        )
+        .is_err());
+        assert!(OtpUri::parse("otpauth://totp/x?secret=GEZD%G").is_err());
+        assert!(!OtpUri::is_uri("GEZDGNBVGY3TQOJQ"));
+    }
+
+    #[test]
+    fn test_otp_type() {
+        assert_eq!("Steam".parse::<OtpType>().unwrap(), OtpType::Steam);
+        assert_eq!("totp".parse::<OtpType>().unwrap(), OtpType::Totp);
+        assert!("hotp".parse::<OtpType>().is_err());
+
+        assert!(OtpType::Totp.is_well_formed("123456", 6));
+        assert!(!OtpType::Totp.is_well_formed("12345", 6));
+        assert!(OtpType::Steam.is_well_formed("VHHQY", 6));
+        assert!(!OtpType::Steam.is_well_formed("VHHQA", 6));
+        assert!(!OtpType::Steam.is_well_formed("12345", 6));
+    }
+}
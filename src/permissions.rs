@@ -0,0 +1,182 @@
+use crate::error::{Context, Error, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Mode of store files and of directories ova creates for them
+#[cfg(unix)]
+pub const PRIVATE_FILE_MODE: u32 = 0o600;
+
+#[cfg(unix)]
+const PRIVATE_DIR_MODE: u32 = 0o700;
+
+/// A store file other users can access, or a directory they can write to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionIssue {
+    pub path: PathBuf,
+    pub mode: u32,
+    pub fixed_mode: u32,
+    pub is_dir: bool,
+}
+
+impl fmt::Display for PermissionIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = if self.is_dir {
+            "writable"
+        } else {
+            "accessible"
+        };
+
+        write!(
+            f,
+            "{} is {} by other users (mode {:o})",
+            self.path.to_string_lossy(),
+            access,
+            self.mode
+        )
+    }
+}
+
+/// Creates `dir` and its missing parents, on Unix only the owner can access
+/// the ones that get created
+pub fn create_private_dir(dir: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(PRIVATE_DIR_MODE);
+    }
+
+    builder
+        .create(dir)
+        .with_context(|| format!("Error creating directory at - {}", dir.to_string_lossy()))
+}
+
+/// Checks the store file at `path` and its directory. A store or directory
+/// owned by another user is an error, loose permissions are returned so they
+/// can be reported and fixed.
+#[cfg(unix)]
+pub fn check(path: &Path) -> Result<Vec<PermissionIssue>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let uid = unsafe { libc::geteuid() };
+    let mut issues: Vec<PermissionIssue> = vec![];
+
+    for (path, is_dir) in [(path, false), (dir, true)] {
+        let metadata = fs::metadata(path)
+            .with_context(|| format!("Error reading metadata of - {}", path.to_string_lossy()))?;
+
+        // root may open anybody's store, e.g. through sudo, without that
+        // being a sign of tampering
+        if metadata.uid() != uid && uid != 0 {
+            return Err(Error::Ownership {
+                path: path.to_path_buf(),
+                uid: metadata.uid(),
+            });
+        }
+
+        let mode = metadata.mode() & 0o7777;
+        // Directories usually are readable, e.g. ~/.config, only someone
+        // who can write to them can swap the store. The sticky bit of /tmp
+        // and friends prevents that.
+        let fixed_mode = if is_dir {
+            if mode & 0o1000 != 0 {
+                mode
+            } else {
+                mode & !0o022
+            }
+        } else {
+            mode & !0o077
+        };
+
+        if fixed_mode != mode {
+            issues.push(PermissionIssue {
+                path: path.to_path_buf(),
+                mode,
+                fixed_mode,
+                is_dir,
+            });
+        }
+    }
+
+    Ok(issues)
+}
+
+#[cfg(not(unix))]
+pub fn check(_path: &Path) -> Result<Vec<PermissionIssue>> {
+    Ok(vec![])
+}
+
+/// Tightens the permissions [`check`] complains about, returning what was
+/// changed
+pub fn fix(path: &Path) -> Result<Vec<PermissionIssue>> {
+    let issues = check(path)?;
+
+    #[cfg(unix)]
+    for issue in &issues {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(&issue.path, fs::Permissions::from_mode(issue.fixed_mode))
+            .with_context(|| {
+                format!(
+                    "Error changing permissions of - {}",
+                    issue.path.to_string_lossy()
+                )
+            })?;
+    }
+
+    Ok(issues)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[test]
+    fn test_create_private_dir() {
+        let dir = PathBuf::new().join("test_private_dir");
+        create_private_dir(&dir.join("vaults")).unwrap();
+
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&dir.join("vaults")), 0o700);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_and_fix() {
+        let dir = PathBuf::new().join("test_permissions_dir");
+        let path = dir.join("ova.store");
+        fs::create_dir(&dir).unwrap();
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o775)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let issues = check(&path).unwrap();
+        assert_eq!(issues.len(), 2);
+        assert_eq!(
+            issues[0].to_string(),
+            "test_permissions_dir/ova.store is accessible by other users (mode 644)"
+        );
+        assert_eq!(issues[1].fixed_mode, 0o755);
+
+        assert_eq!(fix(&path).unwrap().len(), 2);
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&dir), 0o755);
+        assert!(check(&path).unwrap().is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
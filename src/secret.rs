@@ -0,0 +1,299 @@
+use crate::error::{Error, Result};
+use base32::Alphabet;
+use clap::ValueEnum;
+use std::fmt;
+use zeroize::{Zeroize, Zeroizing};
+
+/// RFC 4226 requires shared secrets of at least 128 bits
+pub const MIN_SECRET_BYTES: usize = 16;
+
+const BASE32: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+/// A seed or other sensitive value. The memory holding it is wiped when it's
+/// dropped, kept out of swap where the OS allows and it never shows up in
+/// `Debug` output.
+#[derive(Default, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        let secret = Secret(Zeroizing::new(value));
+        memory::lock(secret.0.as_ptr(), secret.0.len());
+        secret
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Clones get their own locked copy
+impl Clone for Secret {
+    fn clone(&self) -> Self {
+        Secret::new(self.expose().to_string())
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Zeroizing empties the string, so the locked range is taken first
+        let (ptr, len) = (self.0.as_ptr(), self.0.len());
+        self.0.zeroize();
+        memory::unlock(ptr, len);
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret::new(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Secret::new(value.to_string())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SecretEncoding {
+    /// Base32 as shown by most services, spaces, dashes and padding are ignored
+    #[default]
+    Base32,
+    /// Hex encoded secret bytes
+    Hex,
+    /// The secret bytes as typed
+    Raw,
+}
+
+/// Turns a secret given in `encoding` into the normalised Base32 form kept in
+/// the store
+pub fn encode_secret(input: &str, encoding: SecretEncoding) -> Result<Secret> {
+    match encoding {
+        SecretEncoding::Base32 => normalize_secret(input),
+        SecretEncoding::Hex => {
+            let bytes = decode_hex(input)?;
+            check_length(&bytes)?;
+            Ok(Secret::new(base32::encode(BASE32, &bytes)))
+        }
+        SecretEncoding::Raw => {
+            if input.is_empty() {
+                return Err(Error::Otp("Key cannot be empty".to_string()));
+            }
+            check_length(input.as_bytes())?;
+            Ok(Secret::new(base32::encode(BASE32, input.as_bytes())))
+        }
+    }
+}
+
+/// Strips spaces and dashes, uppercases and drops the padding of a Base32
+/// secret, failing if it isn't valid Base32 or is shorter than 128 bits
+pub fn normalize_secret(input: &str) -> Result<Secret> {
+    let stripped: Zeroizing<String> = Zeroizing::new(
+        input
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .collect(),
+    );
+    let secret = Secret::new(stripped.trim_end_matches('=').to_ascii_uppercase());
+
+    if secret.expose().is_empty() {
+        return Err(Error::Otp("Key cannot be empty".to_string()));
+    }
+
+    if let Some(c) = secret
+        .expose()
+        .chars()
+        .find(|c| !matches!(c, 'A'..='Z' | '2'..='7'))
+    {
+        return Err(Error::Otp(format!(
+            "Key is not valid Base32, unexpected character '{}'",
+            c
+        )));
+    }
+
+    let bytes = decode_secret(secret.expose())?;
+    check_length(&bytes)?;
+
+    Ok(secret)
+}
+
+pub fn decode_secret(secret: &str) -> Result<Zeroizing<Vec<u8>>> {
+    // Base32 encodes 5 bytes in 8 characters, leftovers of 1, 3 or 6
+    // characters can't come out of an encoder
+    if matches!(secret.len() % 8, 1 | 3 | 6) {
+        return Err(Error::Otp(
+            "Key is not valid Base32, wrong length".to_string(),
+        ));
+    }
+
+    base32::decode(BASE32, secret)
+        .map(Zeroizing::new)
+        .ok_or_else(|| Error::Otp("Key is not valid Base32".to_string()))
+}
+
+fn check_length(bytes: &[u8]) -> Result<()> {
+    if bytes.len() < MIN_SECRET_BYTES {
+        return Err(Error::Otp(format!(
+            "Key must be at least {} bits long, got {}",
+            MIN_SECRET_BYTES * 8,
+            bytes.len() * 8
+        )));
+    }
+
+    Ok(())
+}
+
+fn decode_hex(input: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let digits: Zeroizing<Vec<u8>> = input
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':' && *c != '-')
+        .map(|c| {
+            c.to_digit(16).map(|d| d as u8).ok_or_else(|| {
+                Error::Otp(format!(
+                    "Key is not valid hex, unexpected character '{}'",
+                    c
+                ))
+            })
+        })
+        .collect::<Result<Vec<u8>>>()
+        .map(Zeroizing::new)?;
+
+    if !digits.len().is_multiple_of(2) {
+        return Err(Error::Otp(
+            "Key is not valid hex, odd number of digits".to_string(),
+        ));
+    }
+
+    Ok(Zeroizing::new(
+        digits
+            .chunks(2)
+            .map(|pair| pair[0] << 4 | pair[1])
+            .collect(),
+    ))
+}
+
+/// Disables core dumps so secrets can't end up in a crash dump, and on Linux
+/// keeps other processes of the user from attaching to this one. Best effort,
+/// failures are ignored.
+pub fn harden_process() {
+    #[cfg(unix)]
+    unsafe {
+        let limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        libc::setrlimit(libc::RLIMIT_CORE, &limit);
+
+        #[cfg(target_os = "linux")]
+        libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0);
+    }
+}
+
+/// Keeps pages holding secrets from being swapped out. mlock doesn't nest,
+/// so unlocking one secret also unlocks others sharing its pages, which is
+/// fine for the short lived processes ova runs as.
+mod memory {
+    #[cfg(unix)]
+    pub fn lock(ptr: *const u8, len: usize) {
+        if len > 0 {
+            unsafe {
+                libc::mlock(ptr as *const libc::c_void, len);
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn unlock(ptr: *const u8, len: usize) {
+        if len > 0 {
+            unsafe {
+                libc::munlock(ptr as *const libc::c_void, len);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn lock(_ptr: *const u8, _len: usize) {}
+
+    #[cfg(not(unix))]
+    pub fn unlock(_ptr: *const u8, _len: usize) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_clone() {
+        let secret = Secret::from("GEZDGNBVGY3TQOJQGEZDGNBVGY");
+        let clone = secret.clone();
+        drop(secret);
+
+        assert_eq!(clone.expose(), "GEZDGNBVGY3TQOJQGEZDGNBVGY");
+        assert_eq!(format!("{:?}", clone), "Secret(***)");
+    }
+
+    #[test]
+    fn test_normalize_secret() {
+        assert_eq!(
+            normalize_secret("gezd gnbv gy3t qojq gezd gnbv gy")
+                .unwrap()
+                .expose(),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY"
+        );
+        assert_eq!(
+            normalize_secret("GEZDG-NBVGY-3TQOJ-QGEZD-GNBVG-Y======")
+                .unwrap()
+                .expose(),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY"
+        );
+    }
+
+    #[test]
+    fn test_normalize_secret_invalid() {
+        assert!(normalize_secret("").is_err());
+        assert!(normalize_secret("  - ").is_err());
+        assert!(normalize_secret("1234567890123456789012345678").is_err());
+        // 80 bits, common but below the RFC 4226 minimum
+        assert!(normalize_secret("JBSWY3DPEHPK3PXP").is_err());
+        // 27 characters can't be produced by Base32 encoding
+        assert!(normalize_secret("GEZDGNBVGY3TQOJQGEZDGNBVGYA").is_err());
+    }
+
+    #[test]
+    fn test_encode_secret_hex() {
+        assert_eq!(
+            encode_secret("31323334353637383930313233343536", SecretEncoding::Hex)
+                .unwrap()
+                .expose(),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY"
+        );
+        assert!(encode_secret("3132", SecretEncoding::Hex).is_err());
+        assert!(encode_secret("313", SecretEncoding::Hex).is_err());
+        assert!(encode_secret("zz", SecretEncoding::Hex).is_err());
+    }
+
+    #[test]
+    fn test_encode_secret_raw() {
+        assert_eq!(
+            encode_secret("1234567890123456", SecretEncoding::Raw)
+                .unwrap()
+                .expose(),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY"
+        );
+        assert!(encode_secret("short", SecretEncoding::Raw).is_err());
+    }
+
+    #[test]
+    fn test_secret_debug_hides_value() {
+        let secret = Secret::from("GEZDGNBVGY3TQOJQGEZDGNBVGY");
+        assert_eq!(format!("{:?}", secret), "Secret(***)");
+        assert_eq!(secret.expose(), "GEZDGNBVGY3TQOJQGEZDGNBVGY");
+    }
+}
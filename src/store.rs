@@ -1,20 +1,38 @@
-use anyhow::*;
+use crate::{
+    atomic,
+    backup::Backups,
+    error::{Context, Error, Result},
+    format::{self, Document, Entry},
+    otp::OtpType,
+    permissions,
+    secret::{self, Secret},
+    utils::{MAX_DIGITS, MIN_DIGITS},
+};
+use std::cell::Cell;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::env;
 use std::env::consts::OS;
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
 
 const STORE_TEMPLATE: &str = "\
 # OVA store file
 # Expected values like
 # KEY = VALUE
+# Wrap a name in double quotes if it contains '=' or starts with '#',
+# e.g. \"work = prod\" = VALUE
 # You can add keys manually
 # or use the 'ova add <name> <key>' command\n";
 
 const STORE_FILE_NAME: &str = "ova.store";
 
+const STORE_ENV_VAR: &str = "OVA_STORE";
+
+const STORE_DATA_DIR_NAME: &str = "ova";
+
 #[cfg(target_os = "linux")]
 const STORE_DIR_NAME: &str = ".config";
 
@@ -25,152 +43,494 @@ const STORE_DIR_NAME: &str = ".config";
 const STORE_DIR_NAME: &str = "AppData/Local";
 
 pub struct Store {
-    pub keys: HashMap<String, String>,
+    pub keys: HashMap<String, Secret>,
     file_path: PathBuf,
+    /// Taken right before the first write, so a command that fails or
+    /// changes nothing leaves no backup behind
+    backups: Cell<Option<Backups>>,
 }
 
 impl Store {
     pub fn new() -> Result<Self> {
+        Self::open(Self::default_path()?)
+    }
+
+    /// Default store location, `OVA_STORE` wins over the XDG directories
+    pub fn default_path() -> Result<PathBuf> {
+        Self::default_path_from(env_var, |path| path.exists())
+    }
+
+    /// Store named by the `OVA_STORE` environment variable
+    pub fn env_path() -> Option<PathBuf> {
+        env_var(STORE_ENV_VAR).map(PathBuf::from)
+    }
+
+    fn default_path_from(
+        var: impl Fn(&str) -> Option<String>,
+        exists: impl Fn(&Path) -> bool,
+    ) -> Result<PathBuf> {
+        if let Some(path) = var(STORE_ENV_VAR) {
+            return Ok(PathBuf::from(path));
+        }
+
         let home_dir = match OS {
-            "linux" => env::var("HOME").with_context(|| "Error getting HOME env variable")?,
-            "macos" => env::var("HOME").with_context(|| "Error getting HOME env variable")?,
-            "windows" => {
-                env::var("USERPROFILE").with_context(|| "Error getting USERPROFILE env variable")?
-            }
-            _ => return Err(anyhow!("Unsupported OS")),
+            "linux" | "macos" => var("HOME")
+                .ok_or_else(|| Error::Config("Error getting HOME env variable".to_string()))?,
+            "windows" => var("USERPROFILE").ok_or_else(|| {
+                Error::Config("Error getting USERPROFILE env variable".to_string())
+            })?,
+            _ => return Err(Error::Config("Unsupported OS".to_string())),
         };
 
-        let path: PathBuf = PathBuf::new()
-            .join(home_dir)
+        if OS == "windows" {
+            return Ok(PathBuf::from(home_dir)
+                .join(STORE_DIR_NAME)
+                .join(STORE_FILE_NAME));
+        }
+
+        let legacy_path = PathBuf::from(&home_dir)
             .join(STORE_DIR_NAME)
             .join(STORE_FILE_NAME);
+        let config_path = match var("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir).join(STORE_FILE_NAME),
+            None => legacy_path.clone(),
+        };
+
+        // Stores created before the XDG variables were respected keep being
+        // used rather than starting an empty one
+        if exists(&config_path) {
+            return Ok(config_path);
+        }
+        if exists(&legacy_path) {
+            return Ok(legacy_path);
+        }
+
+        match var("XDG_DATA_HOME") {
+            Some(dir) => Ok(PathBuf::from(dir)
+                .join(STORE_DATA_DIR_NAME)
+                .join(STORE_FILE_NAME)),
+            None => Ok(config_path),
+        }
+    }
+
+    /// Opens the store at `path`, creating the file and its directory if missing
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path: PathBuf = path.into();
 
         if !path.exists() {
-            Self::create_store_file(&path).with_context(|| {
-                format!("Error creating config file at - {}", path.to_string_lossy())
-            })?;
+            Self::create_store_file(&path)?;
         }
 
         Ok(Store {
-            keys: Self::read_store_file(&path).with_context(|| {
-                format!("Error reading config file at - {}", path.to_string_lossy())
-            })?,
+            keys: Self::read_store_file(&path)?,
             file_path: path,
+            backups: Cell::new(None),
         })
     }
 
-    fn read_store_file(path: &PathBuf) -> Result<HashMap<String, String>> {
-        let file_content = Self::read_store_file_as_string(path)?;
+    /// Backs the store up with `backups` before it's first changed
+    pub fn with_backups(self, backups: Backups) -> Self {
+        self.backups.set(Some(backups));
+        self
+    }
+
+    pub fn file_path(&self) -> &Path {
+        &self.file_path
+    }
 
-        let keys_map: HashMap<String, String> = file_content
+    fn read_store_file(path: &Path) -> Result<HashMap<String, Secret>> {
+        let keys_map: HashMap<String, Secret> = Self::read_document(path)?
+            .entries()
             .into_iter()
-            .filter(|line| !line.is_empty() && !line.starts_with('#'))
-            .filter_map(|line| {
-                let mut split = line.splitn(2, '=');
-                let key = split.next()?.trim().to_owned();
-                let value = split.next()?.trim().to_owned();
-
-                Some((key, value))
-            })
+            .map(|entry| (entry.name, entry.value))
             .collect();
 
         Ok(keys_map)
     }
 
-    fn read_store_file_as_string(path: &PathBuf) -> Result<Vec<String>> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let mut file_content: Vec<String> = vec![];
-
-        for line in reader.lines() {
-            let line = line.unwrap();
-            if !line.is_empty() && !line.starts_with('#') {
-                file_content.push(line);
+    /// Entries with their attributes in the order they appear in the store
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        Ok(Self::read_document(&self.file_path)?.entries())
+    }
+
+    pub fn entry(&self, key: &str) -> Result<Option<Entry>> {
+        Ok(Self::read_document(&self.file_path)?.entry(key))
+    }
+
+    /// Replaces the tags of `key`, an empty list removes them
+    pub fn set_tags(&self, key: &str, tags: &[String]) -> Result<()> {
+        self.set_attributes(key, &[("tags", &tag_values(tags)?)])
+    }
+
+    /// Sets the note of `key`, `None` removes it
+    pub fn set_note(&self, key: &str, note: Option<&str>) -> Result<()> {
+        let values: Vec<String> = note.map(|note| note.to_string()).into_iter().collect();
+        self.set_attributes(key, &[("note", &values)])
+    }
+
+    /// Sets the kind of codes `key` generates, TOTP needs no attribute
+    pub fn set_otp_type(&self, key: &str, otp_type: OtpType) -> Result<()> {
+        self.set_attributes(key, &[("type", &otp_type_values(otp_type))])
+    }
+
+    /// Replaces the recovery codes of `key`, both the unused and the used ones
+    pub fn set_recovery_codes(&self, key: &str, unused: &[String], used: &[String]) -> Result<()> {
+        for code in unused.iter().chain(used) {
+            if code.trim().is_empty() || code.chars().any(char::is_control) {
+                return Err(Error::Invalid(format!("Invalid recovery code '{}'", code)));
+            }
+        }
+
+        self.set_attributes(key, &[("recovery", unused), ("recovery_used", used)])
+    }
+
+    /// Replaces the given attributes of `key` in a single write
+    fn set_attributes(&self, key: &str, attributes: &[(&str, &[String])]) -> Result<()> {
+        let mut document = Self::read_document(&self.file_path)?;
+
+        for (attribute, values) in attributes {
+            if !document.set_attribute(key, attribute, values) {
+                return Err(Error::KeyNotFound(vec![key.to_string()]));
             }
         }
 
+        self.write_document(&document)
+    }
+
+    #[cfg(test)]
+    fn read_store_file_as_string(path: &PathBuf) -> Result<Vec<String>> {
+        let file_content: Vec<String> = fs::read_to_string(path)
+            .with_context(|| "Error reading store file")?
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.to_string())
+            .collect();
+
         Ok(file_content)
     }
 
-    fn create_store_file(path: &PathBuf) -> Result<()> {
-        let mut file = File::create(path)?;
-        file.write_all(STORE_TEMPLATE.as_bytes())?;
-        Ok(())
+    fn create_store_file(path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            permissions::create_private_dir(dir)?;
+        }
+
+        atomic::write_file(path, STORE_TEMPLATE.as_bytes())
+    }
+
+    pub fn insert_into_store(&self, key: &str, value: &str) -> Result<()> {
+        self.add_entry(key, value, OtpType::Totp, None, &[])
     }
 
-    pub fn insert_into_store(&self, key: &String, value: &String) -> Result<()> {
+    /// Adds `key` with the kind of codes it generates, the digits of its
+    /// codes when the key comes with its own and its tags, all in a single
+    /// write
+    pub fn add_entry(
+        &self,
+        key: &str,
+        value: &str,
+        otp_type: OtpType,
+        digits: Option<usize>,
+        tags: &[String],
+    ) -> Result<()> {
         if key.trim().is_empty() || value.trim().is_empty() {
-            return Err(anyhow!("Key cannot be empty"));
+            return Err(Error::Invalid("Key cannot be empty".to_string()));
         }
 
-        if value.trim().len() < 16 {
-            return Err(anyhow!("Key must be at least 16 characters long"));
-        }
+        format::validate_name(key)?;
+
+        let value = secret::normalize_secret(value)?;
 
         if self.keys.contains_key(key) {
-            return Err(anyhow!("Key already exists"));
+            return Err(Error::KeyExists(key.to_string()));
+        }
+
+        let tags = tag_values(tags)?;
+        let digits: Vec<String> = match digits {
+            Some(digits @ MIN_DIGITS..=MAX_DIGITS) => vec![digits.to_string()],
+            Some(digits) => {
+                return Err(Error::Otp(format!(
+                    "Codes must have {} to {} digits, not {}",
+                    MIN_DIGITS, MAX_DIGITS, digits
+                )))
+            }
+            None => vec![],
+        };
+
+        let mut document = Self::read_document(&self.file_path)?;
+        document.push(format::format_entry(key, value.expose()));
+        for (attribute, values) in [
+            ("type", &otp_type_values(otp_type)),
+            ("digits", &digits),
+            ("tags", &tags),
+        ] {
+            document.set_attribute(key, attribute, values);
         }
 
-        let mut file = OpenOptions::new().append(true).open(&self.file_path)?;
+        self.write_document(&document)
+    }
 
-        file.write_all(format!("{} = {}\n", key, value).as_bytes())?;
-        Ok(())
+    pub fn delete_from_store(&self, key: &str) -> Result<()> {
+        self.delete_many_from_store(&[key])
     }
 
-    pub fn delete_from_store(&self, key: &String) -> Result<()> {
-        if key.trim().is_empty() {
-            return Err(anyhow!("Key cannot be empty"));
+    /// Removes all `keys` in a single write, nothing is removed if any of
+    /// them is missing
+    pub fn delete_many_from_store(&self, keys: &[&str]) -> Result<()> {
+        if keys.is_empty() || keys.iter().any(|key| key.trim().is_empty()) {
+            return Err(Error::Invalid("Key cannot be empty".to_string()));
         }
 
-        let (index_to_remove, mut file_content) = Self::find_line_index(key, &self.file_path)?;
+        let mut document = Self::read_document(&self.file_path)?;
+        let mut missing: Vec<String> = vec![];
+
+        for key in keys {
+            match document.entry_range(key) {
+                Some(range) => {
+                    document.lines.drain(range);
+                }
+                None => missing.push(key.to_string()),
+            }
+        }
 
-        if let Some(index) = index_to_remove {
-            file_content.remove(index);
+        if !missing.is_empty() {
+            return Err(Error::KeyNotFound(missing));
         }
 
-        let mut file = File::create(&self.file_path)?;
-        file.write_all(file_content.join("\n").as_bytes())?;
-        Ok(())
+        self.write_document(&document)
     }
 
-    pub fn update_store(&self, key: &String, value: &String) -> Result<()> {
-        if key.trim().is_empty() || value.trim().is_empty() {
-            return Err(anyhow!("Key cannot be empty"));
+    /// Moves entries to the trash, all of them or none if any is missing
+    pub fn trash_many_in_store(&self, keys: &[&str]) -> Result<()> {
+        if keys.is_empty() || keys.iter().any(|key| key.trim().is_empty()) {
+            return Err(Error::Invalid("Key cannot be empty".to_string()));
+        }
+
+        let mut document = Self::read_document(&self.file_path)?;
+        let deleted = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default()
+            .to_string();
+        let mut missing: Vec<String> = vec![];
+
+        for key in keys {
+            if !document.set_attribute(key, format::DELETED, std::slice::from_ref(&deleted)) {
+                missing.push(key.to_string());
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(Error::KeyNotFound(missing));
         }
 
-        if value.trim().len() < 16 {
-            return Err(anyhow!("Key must be at least 16 characters long"));
+        self.write_document(&document)
+    }
+
+    /// Entries in the trash, most recently deleted first
+    pub fn trash(&self) -> Result<Vec<Entry>> {
+        Ok(Self::read_document(&self.file_path)?
+            .trashed()
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect())
+    }
+
+    /// Takes the most recently deleted entry called `key` out of the trash
+    pub fn restore_from_trash(&self, key: &str) -> Result<()> {
+        let mut document = Self::read_document(&self.file_path)?;
+
+        if document.find(key).is_some() {
+            return Err(Error::KeyExists(key.to_string()));
+        }
+
+        let range = document
+            .trashed()
+            .into_iter()
+            .find(|(_, entry)| entry.name == key)
+            .map(|(range, _)| range)
+            .ok_or_else(|| Error::KeyNotFound(vec![key.to_string()]))?;
+        document.untrash(range);
+
+        self.write_document(&document)
+    }
+
+    /// Deletes entries from the trash for good, every entry when `keys` is
+    /// empty. Returns the names of the deleted entries.
+    pub fn purge_trash(&self, keys: &[&str]) -> Result<Vec<String>> {
+        let mut document = Self::read_document(&self.file_path)?;
+        let mut trashed = document.trashed();
+
+        let missing: Vec<String> = keys
+            .iter()
+            .filter(|key| !trashed.iter().any(|(_, entry)| entry.name == **key))
+            .map(|key| key.to_string())
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::KeyNotFound(missing));
+        }
+
+        trashed.retain(|(_, entry)| keys.is_empty() || keys.contains(&entry.name.as_str()));
+        trashed.sort_by_key(|(range, _)| Reverse(range.start));
+        for (range, _) in &trashed {
+            document.lines.drain(range.clone());
+        }
+
+        self.write_document(&document)?;
+        Ok(trashed
+            .into_iter()
+            .rev()
+            .map(|(_, entry)| entry.name)
+            .collect())
+    }
+
+    pub fn update_store(&self, key: &str, value: &str) -> Result<()> {
+        if key.trim().is_empty() || value.trim().is_empty() {
+            return Err(Error::Invalid("Key cannot be empty".to_string()));
         }
 
-        let (index_to_update, mut file_content) = Self::find_line_index(key, &self.file_path)?;
+        let value = secret::normalize_secret(value)?;
+
+        let mut document = Self::read_document(&self.file_path)?;
+
+        let index = document
+            .find(key)
+            .ok_or_else(|| Error::KeyNotFound(vec![key.to_string()]))?;
+        document.lines[index] = format::format_entry(key, value.expose());
 
-        if let Some(index) = index_to_update {
-            file_content[index] = format!("{} = {}", key, value);
+        self.write_document(&document)
+    }
+
+    /// Renames `from` to `to` in place, keeping everything that belongs to the
+    /// entry. An existing `to` is only replaced when `force` is set.
+    pub fn rename_in_store(&self, from: &str, to: &str, force: bool) -> Result<()> {
+        self.duplicate_entry(from, to, force, true)
+    }
+
+    /// Copies `from` to a new entry `to` right after it, see `rename_in_store`
+    pub fn copy_in_store(&self, from: &str, to: &str, force: bool) -> Result<()> {
+        self.duplicate_entry(from, to, force, false)
+    }
+
+    /// Adds `key` with all its attributes to the `target` store, e.g. another
+    /// vault, leaving this store as it is
+    pub fn copy_entry_to(&self, key: &str, target: &Store) -> Result<()> {
+        let source = Self::read_document(&self.file_path)?;
+        let range = source
+            .entry_range(key)
+            .ok_or_else(|| Error::KeyNotFound(vec![key.to_string()]))?;
+        let lines: Zeroizing<Vec<String>> = Zeroizing::new(source.lines[range].to_vec());
+
+        let mut document = Self::read_document(&target.file_path)?;
+        if document.find(key).is_some() {
+            return Err(Error::KeyExists(key.to_string()));
+        }
+        for line in lines.iter() {
+            document.push(line.clone());
         }
 
-        let mut file = File::create(&self.file_path)?;
-        file.write_all(file_content.join("\n").as_bytes())?;
-        Ok(())
+        target.write_document(&document)
     }
 
-    fn find_line_index(key: &String, path: &PathBuf) -> Result<(Option<usize>, Vec<String>)> {
-        let file_content = Self::read_store_file_as_string(path)?;
-        let mut index: Option<usize> = None;
-        for (i, line) in file_content.iter().enumerate() {
-            if line.starts_with(key) {
-                index = Some(i);
-                break;
+    fn duplicate_entry(&self, from: &str, to: &str, force: bool, rename: bool) -> Result<()> {
+        if from.trim().is_empty() {
+            return Err(Error::Invalid("Key cannot be empty".to_string()));
+        }
+
+        format::validate_name(to)?;
+
+        if from == to {
+            return Err(Error::Invalid(
+                "Source and destination are the same key".to_string(),
+            ));
+        }
+
+        let mut document = Self::read_document(&self.file_path)?;
+
+        let source = document
+            .entry_range(from)
+            .ok_or_else(|| Error::KeyNotFound(vec![from.to_string()]))?;
+        let mut entry = document.lines[source].to_vec();
+        let (_, value) = format::parse_entry(&entry[0]).ok_or_else(|| Error::CorruptStore {
+            path: self.file_path.clone(),
+            reason: format!("entry {} can't be parsed", from),
+        })?;
+        entry[0] = format::format_entry(to, &value);
+
+        if let Some(existing) = document.entry_range(to) {
+            if !force {
+                return Err(Error::KeyExists(to.to_string()));
             }
+            document.lines.drain(existing);
+        }
+
+        let source = document
+            .entry_range(from)
+            .ok_or_else(|| Error::KeyNotFound(vec![from.to_string()]))?;
+        if rename {
+            document.lines.splice(source, entry);
+        } else {
+            document.lines.splice(source.end..source.end, entry);
+        }
+
+        self.write_document(&document)
+    }
+
+    fn read_document(path: &Path) -> Result<Document> {
+        let bytes = Zeroizing::new(fs::read(path).with_context(|| {
+            format!("Error reading store file at - {}", path.to_string_lossy())
+        })?);
+        let content = std::str::from_utf8(&bytes).map_err(|err| Error::CorruptStore {
+            path: path.to_path_buf(),
+            reason: format!("not valid UTF-8 ({})", err),
+        })?;
+
+        Ok(Document::parse(content))
+    }
+
+    fn write_document(&self, document: &Document) -> Result<()> {
+        if let Some(backups) = self.backups.take() {
+            backups.create()?;
+        }
+
+        let content = Zeroizing::new(document.to_string());
+        atomic::write_file(&self.file_path, content.as_bytes())
+    }
+}
+
+/// The `tags` attribute for `tags`, one comma separated line
+fn tag_values(tags: &[String]) -> Result<Vec<String>> {
+    for tag in tags {
+        if tag.trim().is_empty() || tag.contains(',') || tag.chars().any(char::is_control) {
+            return Err(Error::Invalid(format!("Invalid tag '{}'", tag)));
         }
+    }
+
+    let tags: Vec<String> = tags.iter().map(|tag| tag.trim().to_string()).collect();
+    if tags.is_empty() {
+        Ok(vec![])
+    } else {
+        Ok(vec![tags.join(", ")])
+    }
+}
 
-        Ok((index, file_content))
+/// The `type` attribute for `otp_type`, plain TOTP goes without
+fn otp_type_values(otp_type: OtpType) -> Vec<String> {
+    match otp_type {
+        OtpType::Totp => vec![],
+        otp_type => vec![otp_type.to_string()],
     }
 }
 
+/// Environment variable `name`, unset when it's empty
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::remove_file;
+    use std::fs::{remove_file, File, OpenOptions};
+    use std::io::Write;
     use std::path::PathBuf;
 
     #[test]
@@ -183,25 +543,115 @@ mod tests {
         remove_file(path).unwrap();
     }
 
+    #[test]
+    fn test_open_creates_missing_directory() {
+        let dir = PathBuf::new().join("test_open_dir");
+        let path = dir.join("nested").join("ova.store");
+
+        let store = Store::open(&path).unwrap();
+        assert!(store.keys.is_empty());
+        assert!(path.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_corrupt_store() {
+        let path = PathBuf::new().join("test_corrupt.store");
+        fs::write(&path, b"github = \xff\xfe\n").unwrap();
+
+        let result = Store::open(&path);
+        assert!(matches!(result, Err(Error::CorruptStore { .. })));
+
+        remove_file(path).unwrap();
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_default_path() {
+        let vars = |overrides: Vec<(&'static str, &'static str)>| {
+            move |name: &str| {
+                overrides
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        let none = |_: &Path| false;
+
+        let path = Store::default_path_from(vars(vec![("HOME", "/home/ova")]), none).unwrap();
+        assert_eq!(path, PathBuf::from("/home/ova/.config/ova.store"));
+
+        let path = Store::default_path_from(
+            vars(vec![
+                ("HOME", "/home/ova"),
+                ("XDG_CONFIG_HOME", "/xdg/config"),
+            ]),
+            none,
+        )
+        .unwrap();
+        assert_eq!(path, PathBuf::from("/xdg/config/ova.store"));
+
+        let path = Store::default_path_from(
+            vars(vec![("HOME", "/home/ova"), ("XDG_DATA_HOME", "/xdg/data")]),
+            none,
+        )
+        .unwrap();
+        assert_eq!(path, PathBuf::from("/xdg/data/ova/ova.store"));
+
+        let path = Store::default_path_from(
+            vars(vec![
+                ("HOME", "/home/ova"),
+                ("XDG_DATA_HOME", "/xdg/data"),
+                ("OVA_STORE", "/mnt/vault/ova.store"),
+            ]),
+            none,
+        )
+        .unwrap();
+        assert_eq!(path, PathBuf::from("/mnt/vault/ova.store"));
+
+        // A store in ~/.config is kept when the XDG variables point elsewhere
+        let legacy = |path: &Path| path == Path::new("/home/ova/.config/ova.store");
+        let path = Store::default_path_from(
+            vars(vec![
+                ("HOME", "/home/ova"),
+                ("XDG_CONFIG_HOME", "/xdg/config"),
+                ("XDG_DATA_HOME", "/xdg/data"),
+            ]),
+            legacy,
+        )
+        .unwrap();
+        assert_eq!(path, PathBuf::from("/home/ova/.config/ova.store"));
+
+        let config = |path: &Path| path == Path::new("/xdg/config/ova.store");
+        let path = Store::default_path_from(
+            vars(vec![
+                ("HOME", "/home/ova"),
+                ("XDG_CONFIG_HOME", "/xdg/config"),
+                ("XDG_DATA_HOME", "/xdg/data"),
+            ]),
+            config,
+        )
+        .unwrap();
+        assert_eq!(path, PathBuf::from("/xdg/config/ova.store"));
+    }
+
     #[test]
     fn test_read_store_file_as_string() {
         let path = PathBuf::new().join("test_read_as_string.store");
         let _ = File::create(&path);
 
         let key = "test_key".to_string();
-        let value = "1234567890123456".to_string();
+        let value = "GEZDGNBVGY3TQOJQGEZDGNBVGY".to_string();
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(&path)
-            .unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
         file.write_all(format!("{} = {}", key, value).as_bytes())
             .unwrap();
 
         let file_content = Store::read_store_file_as_string(&path).unwrap();
         assert_eq!(file_content.len(), 1);
-        assert_eq!(file_content[0], "test_key = 1234567890123456");
+        assert_eq!(file_content[0], "test_key = GEZDGNBVGY3TQOJQGEZDGNBVGY");
 
         remove_file(path).unwrap();
     }
@@ -231,13 +681,9 @@ mod tests {
         let _ = File::create(&path);
 
         let key = "test_key".to_string();
-        let value = "1234567890123456".to_string();
+        let value = "GEZDGNBVGY3TQOJQGEZDGNBVGY".to_string();
 
-        let mut file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(&path)
-            .unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
         file.write_all(format!("{} = {}", key, value).as_bytes())
             .unwrap();
 
@@ -245,7 +691,7 @@ mod tests {
 
         file_content.iter().for_each(|entry| {
             assert_eq!(entry.0, "test_key");
-            assert_eq!(entry.1, "1234567890123456");
+            assert_eq!(entry.1.expose(), "GEZDGNBVGY3TQOJQGEZDGNBVGY");
         });
 
         remove_file(path).unwrap();
@@ -258,16 +704,17 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "test_key".to_string();
-        let value = "abcdefghijklmnop".to_string();
+        let value = "MFRGGZDFMZTWQ2LKNNWG23TPOA".to_string();
 
         store.insert_into_store(&key, &value).unwrap();
 
         let file_content = Store::read_store_file_as_string(&path).unwrap();
         assert_eq!(file_content.len(), 1);
-        assert_eq!(file_content[0], "test_key = abcdefghijklmnop");
+        assert_eq!(file_content[0], "test_key = MFRGGZDFMZTWQ2LKNNWG23TPOA");
 
         remove_file(path).unwrap();
     }
@@ -279,6 +726,7 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "".to_string();
@@ -297,6 +745,7 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "test_key".to_string();
@@ -315,6 +764,7 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "".to_string();
@@ -333,10 +783,11 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "abcdefghijklmnop".to_string();
-        let value = "abcdefghijklmno".to_string();
+        let value = "MFRGGZDFMZTWQ2LKNNWG23TP".to_string();
 
         let result = store.insert_into_store(&key, &value);
         assert!(result.is_err());
@@ -351,15 +802,18 @@ mod tests {
         let mut store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "test_key".to_string();
-        let value = "abcdefghijklmnop".to_string();
+        let value = "MFRGGZDFMZTWQ2LKNNWG23TPOA".to_string();
 
         store.insert_into_store(&key, &value).unwrap();
-        store.keys.insert(key.clone(), value.clone());
+        store.keys.insert(key.clone(), Secret::from(value.clone()));
         let result = store.insert_into_store(&key, &value);
         assert!(result.is_err());
+
+        remove_file(path).unwrap();
     }
 
     #[test]
@@ -369,10 +823,11 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "test_key".to_string();
-        let value = "1234567890123456".to_string();
+        let value = "GEZDGNBVGY3TQOJQGEZDGNBVGY".to_string();
 
         store.insert_into_store(&key, &value).unwrap();
         store.delete_from_store(&key).unwrap();
@@ -383,6 +838,30 @@ mod tests {
         remove_file(path).unwrap();
     }
 
+    #[test]
+    fn test_backup_before_first_write() {
+        let dir = PathBuf::new().join("test_store_backups");
+        let path = PathBuf::new().join("test_store_backups.store");
+        fs::write(&path, "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\n").unwrap();
+        let backups = || Backups::new(&path, Some(&dir), 10);
+        let store = Store::open(&path).unwrap().with_backups(backups());
+
+        // Nothing is backed up when nothing gets written
+        assert!(store.delete_from_store("missing").is_err());
+        assert!(backups().list().unwrap().is_empty());
+
+        store.delete_from_store("github").unwrap();
+        store
+            .insert_into_store("gitlab", "GEZDGNBVGY3TQOJQGEZDGNBVGY")
+            .unwrap();
+        let list = backups().list().unwrap();
+        assert_eq!(list.len(), 1);
+        assert!(list[0].read().unwrap().entry("github").is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+        remove_file(path).unwrap();
+    }
+
     #[test]
     fn test_delete_from_store_with_empty_key() {
         let path = PathBuf::new().join("test_delete_empty_key.store");
@@ -390,6 +869,7 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "".to_string();
@@ -407,19 +887,546 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "test_key".to_string();
-        let value = "1234567890123456".to_string();
+        let value = "GEZDGNBVGY3TQOJQGEZDGNBVGY".to_string();
 
         store.insert_into_store(&key, &value).unwrap();
         store
-            .update_store(&key, &"1234567890123456".to_string())
+            .update_store(&key, "GEZDGNBVGY3TQOJQGEZDGNBVGY")
             .unwrap();
 
         let file_content = Store::read_store_file_as_string(&path).unwrap();
         assert_eq!(file_content.len(), 1);
-        assert_eq!(file_content[0], "test_key = 1234567890123456");
+        assert_eq!(file_content[0], "test_key = GEZDGNBVGY3TQOJQGEZDGNBVGY");
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_delete_from_store_with_prefix_collision() {
+        let path = PathBuf::new().join("test_delete_prefix.store");
+        let _ = File::create(&path);
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        let value = "GEZDGNBVGY3TQOJQGEZDGNBVGY".to_string();
+        store.insert_into_store("github", &value).unwrap();
+        store.insert_into_store("git", &value).unwrap();
+        store.delete_from_store("git").unwrap();
+
+        let file_content = Store::read_store_file_as_string(&path).unwrap();
+        assert_eq!(file_content, vec!["github = GEZDGNBVGY3TQOJQGEZDGNBVGY"]);
+
+        let result = store.delete_from_store("git");
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::KeyNotFound(names) if names == vec!["git".to_string()]
+        ));
+        let file_content = Store::read_store_file_as_string(&path).unwrap();
+        assert_eq!(file_content, vec!["github = GEZDGNBVGY3TQOJQGEZDGNBVGY"]);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_update_store_with_prefix_collision() {
+        let path = PathBuf::new().join("test_update_prefix.store");
+        let _ = File::create(&path);
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        store
+            .insert_into_store("github", "GEZDGNBVGY3TQOJQGEZDGNBVGY")
+            .unwrap();
+        store
+            .insert_into_store("git", "GEZDGNBVGY3TQOJQGEZDGNBVGY")
+            .unwrap();
+        store
+            .update_store("git", "MFRGGZDFMZTWQ2LKNNWG23TPOA")
+            .unwrap();
+
+        let keys = Store::read_store_file(&path).unwrap();
+        assert_eq!(keys["github"].expose(), "GEZDGNBVGY3TQOJQGEZDGNBVGY");
+        assert_eq!(keys["git"].expose(), "MFRGGZDFMZTWQ2LKNNWG23TPOA");
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_insert_into_store_with_unsafe_names() {
+        let path = PathBuf::new().join("test_insert_unsafe.store");
+        let _ = File::create(&path);
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        let value = "GEZDGNBVGY3TQOJQGEZDGNBVGY".to_string();
+        let names = ["work = prod", "#hashtag", "  padded", "\"quoted\""];
+        for name in names {
+            store.insert_into_store(name, &value).unwrap();
+        }
+
+        let keys = Store::read_store_file(&path).unwrap();
+        assert_eq!(keys.len(), names.len());
+        for name in names {
+            assert_eq!(keys[name].expose(), value);
+        }
+
+        store.delete_from_store("#hashtag").unwrap();
+        let keys = Store::read_store_file(&path).unwrap();
+        assert!(!keys.contains_key("#hashtag"));
+        assert_eq!(keys.len(), names.len() - 1);
+
+        let result = store.insert_into_store("new\nline", &value);
+        assert!(result.is_err());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_keeps_comments_and_layout() {
+        let path = PathBuf::new().join("test_rewrite_lossless.store");
+        let content = format!(
+            "{}\n# personal\ngithub = GEZDGNBVGY3TQOJQGEZDGNBVGY\n\n# work\ngit = GEZDGNBVGY3TQOJQGEZDGNBVGY\ngitlab = GEZDGNBVGY3TQOJQGEZDGNBVGY\n",
+            STORE_TEMPLATE
+        );
+        fs::write(&path, &content).unwrap();
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        store
+            .update_store("git", "MFRGGZDFMZTWQ2LKNNWG23TPOA")
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            content.replace(
+                "git = GEZDGNBVGY3TQOJQGEZDGNBVGY",
+                "git = MFRGGZDFMZTWQ2LKNNWG23TPOA"
+            )
+        );
+
+        store.delete_from_store("git").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            content.replace("git = GEZDGNBVGY3TQOJQGEZDGNBVGY\n", "")
+        );
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rewrite_keeps_missing_trailing_newline() {
+        let path = PathBuf::new().join("test_rewrite_no_newline.store");
+        fs::write(
+            &path,
+            "# header\ngithub = GEZDGNBVGY3TQOJQGEZDGNBVGY\ngit = GEZDGNBVGY3TQOJQGEZDGNBVGY",
+        )
+        .unwrap();
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        store.delete_from_store("git").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# header\ngithub = GEZDGNBVGY3TQOJQGEZDGNBVGY"
+        );
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_delete_many_from_store() {
+        let path = PathBuf::new().join("test_delete_many.store");
+        let _ = File::create(&path);
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        for key in ["github", "gitlab", "aws"] {
+            store
+                .insert_into_store(key, "GEZDGNBVGY3TQOJQGEZDGNBVGY")
+                .unwrap();
+        }
+
+        let result = store.delete_many_from_store(&["github", "missing"]);
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::KeyNotFound(names) if names == vec!["missing".to_string()]
+        ));
+        assert_eq!(Store::read_store_file(&path).unwrap().len(), 3);
+
+        store.delete_many_from_store(&["github", "gitlab"]).unwrap();
+        let file_content = Store::read_store_file_as_string(&path).unwrap();
+        assert_eq!(file_content, vec!["aws = GEZDGNBVGY3TQOJQGEZDGNBVGY"]);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_update_store_with_missing_key() {
+        let path = PathBuf::new().join("test_update_missing.store");
+        let _ = File::create(&path);
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        let result = store.update_store("github", "GEZDGNBVGY3TQOJQGEZDGNBVGY");
+        assert!(matches!(result.unwrap_err(), Error::KeyNotFound(_)));
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_rename_in_store() {
+        let path = PathBuf::new().join("test_rename.store");
+        fs::write(
+            &path,
+            "# personal\ngithub = GEZDGNBVGY3TQOJQGEZDGNBVGY\n# work\ngitlab = MFRGGZDFMZTWQ2LKNNWG23TPOA\n",
+        )
+        .unwrap();
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        store
+            .rename_in_store("github", "work = github", false)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# personal\n\"work = github\" = GEZDGNBVGY3TQOJQGEZDGNBVGY\n# work\ngitlab = MFRGGZDFMZTWQ2LKNNWG23TPOA\n"
+        );
+
+        assert!(store.rename_in_store("github", "aws", false).is_err());
+        assert!(store.rename_in_store("gitlab", "gitlab", false).is_err());
+        assert!(store
+            .rename_in_store("gitlab", "work = github", false)
+            .is_err());
+
+        store
+            .rename_in_store("gitlab", "work = github", true)
+            .unwrap();
+        let keys = Store::read_store_file(&path).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys["work = github"].expose(), "MFRGGZDFMZTWQ2LKNNWG23TPOA");
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_copy_in_store() {
+        let path = PathBuf::new().join("test_copy.store");
+        fs::write(
+            &path,
+            "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\ngitlab = MFRGGZDFMZTWQ2LKNNWG23TPOA\n",
+        )
+        .unwrap();
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        store
+            .copy_in_store("github", "github-backup", false)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "github = GEZDGNBVGY3TQOJQGEZDGNBVGY\ngithub-backup = GEZDGNBVGY3TQOJQGEZDGNBVGY\ngitlab = MFRGGZDFMZTWQ2LKNNWG23TPOA\n"
+        );
+
+        assert!(store.copy_in_store("github", "gitlab", false).is_err());
+        store.copy_in_store("github", "gitlab", true).unwrap();
+        let keys = Store::read_store_file(&path).unwrap();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys["gitlab"].expose(), "GEZDGNBVGY3TQOJQGEZDGNBVGY");
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_set_tags() {
+        let path = PathBuf::new().join("test_set_tags.store");
+        let _ = File::create(&path);
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        store
+            .insert_into_store("github", "GEZDGNBVGY3TQOJQGEZDGNBVGY")
+            .unwrap();
+        store
+            .set_tags("github", &["work".to_string(), "dev".to_string()])
+            .unwrap();
+        store
+            .insert_into_store("gitlab", "GEZDGNBVGY3TQOJQGEZDGNBVGY")
+            .unwrap();
+
+        let entries = store.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].tags(), vec!["work", "dev"]);
+        assert!(entries[1].tags().is_empty());
+        assert_eq!(Store::read_store_file(&path).unwrap().len(), 2);
+
+        assert!(store.set_tags("github", &["a,b".to_string()]).is_err());
+        assert!(store.set_tags("missing", &["work".to_string()]).is_err());
+
+        store
+            .rename_in_store("github", "work/github", false)
+            .unwrap();
+        let entries = store.entries().unwrap();
+        assert_eq!(entries[0].name, "work/github");
+        assert_eq!(entries[0].tags(), vec!["work", "dev"]);
+
+        store.delete_from_store("work/github").unwrap();
+        let file_content = Store::read_store_file_as_string(&path).unwrap();
+        assert_eq!(file_content, vec!["gitlab = GEZDGNBVGY3TQOJQGEZDGNBVGY"]);
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_trash() {
+        let path = PathBuf::new().join("test_trash.store");
+        let _ = File::create(&path);
+        let mut store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        store
+            .insert_into_store("github", "GEZDGNBVGY3TQOJQGEZDGNBVGY")
+            .unwrap();
+        store
+            .insert_into_store("gitlab", "GEZDGNBVGY3TQOJQGEZDGNBVGY")
+            .unwrap();
+        assert!(store.trash_many_in_store(&["github", "missing"]).is_err());
+        store.trash_many_in_store(&["github"]).unwrap();
+
+        store.keys = Store::read_store_file(&path).unwrap();
+        assert!(!store.keys.contains_key("github"));
+        assert_eq!(store.trash().unwrap()[0].name, "github");
+
+        // The name is free again while the old entry sits in the trash
+        store
+            .insert_into_store("github", "MFRGGZDFMZTWQ2LKNNWG23TPOA")
+            .unwrap();
+        assert!(store.restore_from_trash("github").is_err());
+        store.delete_from_store("github").unwrap();
+        store.restore_from_trash("github").unwrap();
+        assert_eq!(
+            store.entry("github").unwrap().unwrap().value.expose(),
+            "GEZDGNBVGY3TQOJQGEZDGNBVGY"
+        );
+        assert!(store.trash().unwrap().is_empty());
+
+        store.trash_many_in_store(&["github", "gitlab"]).unwrap();
+        assert!(store.purge_trash(&["missing"]).is_err());
+        assert_eq!(store.purge_trash(&["gitlab"]).unwrap(), vec!["gitlab"]);
+        assert_eq!(store.purge_trash(&[]).unwrap(), vec!["github"]);
+        assert!(Store::read_store_file_as_string(&path).unwrap().is_empty());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_set_note_and_recovery_codes() {
+        let path = PathBuf::new().join("test_recovery_codes.store");
+        let _ = File::create(&path);
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        store
+            .insert_into_store("github", "GEZDGNBVGY3TQOJQGEZDGNBVGY")
+            .unwrap();
+        store.set_note("github", Some("personal\nno sms")).unwrap();
+        store
+            .set_recovery_codes(
+                "github",
+                &["aaaa-1111".to_string(), "bbbb 2222".to_string()],
+                &["cccc-3333".to_string()],
+            )
+            .unwrap();
+
+        let entry = store.entry("github").unwrap().unwrap();
+        assert_eq!(entry.note(), Some("personal\nno sms"));
+        assert_eq!(entry.recovery_codes(), vec!["aaaa-1111", "bbbb 2222"]);
+        assert_eq!(entry.used_recovery_codes(), vec!["cccc-3333"]);
+        assert_eq!(store.entry("github").unwrap().unwrap().tags().len(), 0);
+
+        store.set_note("github", None).unwrap();
+        store.set_recovery_codes("github", &[], &[]).unwrap();
+        let file_content = Store::read_store_file_as_string(&path).unwrap();
+        assert_eq!(file_content, vec!["github = GEZDGNBVGY3TQOJQGEZDGNBVGY"]);
+
+        assert!(store
+            .set_recovery_codes("github", &[" ".to_string()], &[])
+            .is_err());
+        assert!(store.set_note("missing", Some("note")).is_err());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_set_otp_type() {
+        let path = PathBuf::new().join("test_set_otp_type.store");
+        let _ = File::create(&path);
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        store
+            .insert_into_store("steam", "GEZDGNBVGY3TQOJQGEZDGNBVGY")
+            .unwrap();
+        store.set_otp_type("steam", OtpType::Steam).unwrap();
+
+        let file_content = Store::read_store_file_as_string(&path).unwrap();
+        assert_eq!(
+            file_content,
+            vec!["steam = GEZDGNBVGY3TQOJQGEZDGNBVGY", "    type = steam"]
+        );
+        assert_eq!(
+            store.entry("steam").unwrap().unwrap().otp_type().unwrap(),
+            OtpType::Steam
+        );
+
+        store.set_otp_type("steam", OtpType::Totp).unwrap();
+        let file_content = Store::read_store_file_as_string(&path).unwrap();
+        assert_eq!(file_content, vec!["steam = GEZDGNBVGY3TQOJQGEZDGNBVGY"]);
+        assert!(store.set_otp_type("missing", OtpType::Steam).is_err());
+
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_copy_entry_to() {
+        let source_path = PathBuf::new().join("test_copy_entry_source.store");
+        let target_path = PathBuf::new().join("test_copy_entry_target.store");
+        fs::write(&target_path, "# work vault\n").unwrap();
+        let _ = File::create(&source_path);
+        let source = Store {
+            keys: HashMap::new(),
+            file_path: source_path.clone(),
+            backups: Cell::new(None),
+        };
+        let target = Store::open(&target_path).unwrap();
+
+        source
+            .add_entry(
+                "github",
+                "GEZDGNBVGY3TQOJQGEZDGNBVGY",
+                OtpType::Steam,
+                None,
+                &["work".to_string()],
+            )
+            .unwrap();
+        source.set_note("github", Some("personal")).unwrap();
+        source
+            .set_recovery_codes(
+                "github",
+                &["aaaa-1111".to_string()],
+                &["bbbb-2222".to_string()],
+            )
+            .unwrap();
+
+        source.copy_entry_to("github", &target).unwrap();
+        assert_eq!(
+            target.entry("github").unwrap(),
+            source.entry("github").unwrap()
+        );
+        let entry = target.entry("github").unwrap().unwrap();
+        assert_eq!(entry.note(), Some("personal"));
+        assert_eq!(entry.recovery_codes(), vec!["aaaa-1111"]);
+        assert_eq!(entry.used_recovery_codes(), vec!["bbbb-2222"]);
+        assert_eq!(entry.otp_type().unwrap(), OtpType::Steam);
+
+        assert!(matches!(
+            source.copy_entry_to("github", &target),
+            Err(Error::KeyExists(_))
+        ));
+        assert!(matches!(
+            source.copy_entry_to("missing", &target),
+            Err(Error::KeyNotFound(_))
+        ));
+
+        remove_file(source_path).unwrap();
+        remove_file(target_path).unwrap();
+    }
+
+    #[test]
+    fn test_add_entry() {
+        let path = PathBuf::new().join("test_add_entry.store");
+        let _ = File::create(&path);
+        let store = Store {
+            keys: HashMap::new(),
+            file_path: path.clone(),
+            backups: Cell::new(None),
+        };
+
+        store
+            .add_entry(
+                "github",
+                "GEZDGNBVGY3TQOJQGEZDGNBVGY",
+                OtpType::Totp,
+                Some(8),
+                &["work".to_string()],
+            )
+            .unwrap();
+        let entry = store.entry("github").unwrap().unwrap();
+        assert_eq!(entry.digits().unwrap(), Some(8));
+        assert_eq!(entry.tags(), vec!["work"]);
+        assert_eq!(entry.otp_type().unwrap(), OtpType::Totp);
+
+        // Nothing is written when one of the attributes is invalid
+        assert!(store
+            .add_entry(
+                "steam",
+                "GEZDGNBVGY3TQOJQGEZDGNBVGY",
+                OtpType::Steam,
+                None,
+                &["a,b".to_string()],
+            )
+            .is_err());
+        assert!(store
+            .add_entry(
+                "steam",
+                "GEZDGNBVGY3TQOJQGEZDGNBVGY",
+                OtpType::Steam,
+                Some(5),
+                &[],
+            )
+            .is_err());
+        assert!(store.entry("steam").unwrap().is_none());
 
         remove_file(path).unwrap();
     }
@@ -431,10 +1438,11 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "".to_string();
-        let value = "1234567890123456".to_string();
+        let value = "GEZDGNBVGY3TQOJQGEZDGNBVGY".to_string();
 
         let result = store.update_store(&key, &value);
         assert!(result.is_err());
@@ -449,6 +1457,7 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "test_key".to_string();
@@ -467,6 +1476,7 @@ mod tests {
         let store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "".to_string();
@@ -485,14 +1495,17 @@ mod tests {
         let mut store = Store {
             keys: HashMap::new(),
             file_path: path.clone(),
+            backups: Cell::new(None),
         };
 
         let key = "abcdefghijklmnop".to_string();
-        let value = "abcdefghijklmno".to_string();
-        let insert_value = "abcdefghijklmnop".to_string();
+        let value = "MFRGGZDFMZTWQ2LKNNWG23TP".to_string();
+        let insert_value = "MFRGGZDFMZTWQ2LKNNWG23TPOA".to_string();
 
         store.insert_into_store(&key, &insert_value).unwrap();
-        store.keys.insert(key.clone(), insert_value.clone());
+        store
+            .keys
+            .insert(key.clone(), Secret::from(insert_value.clone()));
         let result = store.update_store(&key, &value);
         assert!(result.is_err());
 
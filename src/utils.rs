@@ -1,25 +1,340 @@
-use anyhow::{anyhow, Result};
-use totp_rs::{Algorithm, Secret, TOTP};
+use crate::error::{Error, Result};
+use crate::format::Entry;
+use crate::otp::{Clock, Otp, OtpType};
+use crate::secret::Secret;
+use std::path::{Path, PathBuf};
 
-pub fn get_2fa_code(key: &str) -> Result<String> {
-    if key.trim().is_empty() {
-        return Err(anyhow!("Key cannot be empty"));
+pub const DEFAULT_DIGITS: usize = 6;
+
+/// Range of code lengths services use and totp-rs accepts
+pub const MIN_DIGITS: usize = 6;
+pub const MAX_DIGITS: usize = 8;
+
+/// TOTP code of `key` at the time `clock` reports
+pub fn get_2fa_code(key: &Secret, digits: usize, clock: &dyn Clock) -> Result<String> {
+    Otp::new(key, digits)?.totp(clock)
+}
+
+/// Code of `entry` at the time `clock` reports, of the type the entry is for.
+/// `digits` applies unless the entry has digits of its own.
+pub fn get_entry_code(entry: &Entry, digits: usize, clock: &dyn Clock) -> Result<String> {
+    match entry.otp_type()? {
+        OtpType::Totp => get_2fa_code(&entry.value, entry.digits()?.unwrap_or(digits), clock),
+        OtpType::Steam => Otp::steam(&entry.value)?.totp(clock),
+    }
+}
+
+/// Canonical form of `path`, or that of its directory when the file doesn't
+/// exist yet, so a store is known by the same path before and after it's
+/// created
+pub fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| match (path.parent(), path.file_name()) {
+            (Some(dir), Some(file)) => {
+                let dir = if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    dir
+                };
+                dir.canonicalize().map(|dir| dir.join(file))
+            }
+            _ => Ok(path.to_path_buf()),
+        })
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Whether `name` should be treated as a glob pattern rather than a key name
+pub fn is_glob(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Matches `text` against a glob `pattern` where `*` matches any run of
+/// characters and `?` matches exactly one
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM:SS UTC`
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+
+    // Days to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+/// Parses the number of digits of generated codes, 6 to 8 as that's what
+/// services use and totp-rs accepts
+pub fn parse_digits(input: &str) -> Result<usize> {
+    match input.trim().parse() {
+        Ok(digits @ MIN_DIGITS..=MAX_DIGITS) => Ok(digits),
+        _ => Err(Error::Config(format!(
+            "digits must be a number between {} and {}",
+            MIN_DIGITS, MAX_DIGITS
+        ))),
+    }
+}
+
+/// Parses seconds since the Unix epoch or an RFC 3339 date and time such as
+/// `2024-05-01T12:00:00Z` or `2024-05-01T14:00:00+02:00`
+pub fn parse_timestamp(input: &str) -> Result<u64> {
+    let invalid = || {
+        Error::Invalid(format!(
+            "Invalid time '{}', expected seconds since the Unix epoch or RFC 3339",
+            input
+        ))
+    };
+    let input = input.trim();
+
+    if let Ok(secs) = input.parse::<u64>() {
+        return Ok(secs);
+    }
+
+    let number = |range: std::ops::Range<usize>| -> Result<i64> {
+        input
+            .get(range)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let separator = |index: usize, allowed: &[u8]| -> Result<()> {
+        match input.as_bytes().get(index) {
+            Some(b) if allowed.contains(b) => Ok(()),
+            _ => Err(invalid()),
+        }
+    };
+
+    separator(4, b"-")?;
+    separator(7, b"-")?;
+    separator(10, b"Tt ")?;
+    separator(13, b":")?;
+    separator(16, b":")?;
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+
+    // Fractions of a second don't change the code, they're skipped
+    let mut rest = &input[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if digits == 0 {
+            return Err(invalid());
+        }
+        rest = &fraction[digits..];
+    }
+
+    let offset = match rest.as_bytes() {
+        [b'Z' | b'z'] => 0,
+        [sign @ (b'+' | b'-'), h1, h2, b':', m1, m2]
+            if [h1, h2, m1, m2].iter().all(|b| b.is_ascii_digit()) =>
+        {
+            let hours = i64::from((h1 - b'0') * 10 + (h2 - b'0'));
+            let minutes = i64::from((m1 - b'0') * 10 + (m2 - b'0'));
+            let offset = hours * 3_600 + minutes * 60;
+            if *sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+        _ => return Err(invalid()),
+    };
+
+    let days_in_month = match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return Err(invalid());
     }
 
-    let secret = Secret::Encoded(key.to_string());
-    let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, secret.to_bytes().unwrap())?;
-    let code = totp.generate_current()?;
-    Ok(code)
+    // Civil date to days, the inverse of format_timestamp
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let secs = days * 86_400 + hour * 3_600 + minute * 60 + second - offset;
+    u64::try_from(secs).map_err(|_| invalid())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::otp::{FixedClock, SystemClock};
 
     #[test]
     fn test_get_2fa_code_with_empty_key() {
-        let key = "";
-        let code = get_2fa_code(key);
+        let key = &Secret::from("");
+        let code = get_2fa_code(key, DEFAULT_DIGITS, &SystemClock);
         assert!(code.is_err());
     }
+
+    #[test]
+    fn test_get_2fa_code_digits() {
+        let key = &Secret::from("JBSWY3DPEHPK3PXPJBSWY3DPEHPK3PXP");
+
+        assert_eq!(get_2fa_code(key, 6, &SystemClock).unwrap().len(), 6);
+        assert_eq!(get_2fa_code(key, 8, &SystemClock).unwrap().len(), 8);
+        assert!(get_2fa_code(key, 5, &SystemClock).is_err());
+    }
+
+    #[test]
+    fn test_get_2fa_code_at() {
+        let key = &Secret::from("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+
+        assert_eq!(
+            get_2fa_code(key, 8, &FixedClock(1_234_567_890)).unwrap(),
+            "89005924"
+        );
+    }
+
+    #[test]
+    fn test_get_entry_code() {
+        let mut entry = Entry {
+            name: "steam".to_string(),
+            value: Secret::from("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"),
+            attributes: vec![],
+        };
+        let clock = FixedClock(1_234_567_890);
+
+        assert_eq!(get_entry_code(&entry, 8, &clock).unwrap(), "89005924");
+
+        entry
+            .attributes
+            .push(("digits".to_string(), "8".to_string()));
+        assert_eq!(get_entry_code(&entry, 6, &clock).unwrap(), "89005924");
+        entry.attributes.clear();
+
+        entry
+            .attributes
+            .push(("type".to_string(), "steam".to_string()));
+        assert_eq!(get_entry_code(&entry, 8, &clock).unwrap(), "VHHQY");
+
+        entry.attributes[0].1 = "yubikey".to_string();
+        assert!(get_entry_code(&entry, 8, &clock).is_err());
+    }
+
+    #[test]
+    fn test_get_2fa_code_with_invalid_key() {
+        assert!(get_2fa_code(
+            &Secret::from("not base32 at all!"),
+            DEFAULT_DIGITS,
+            &SystemClock
+        )
+        .is_err());
+        assert!(get_2fa_code(
+            &Secret::from("JBSWY3DPEHPK3PXP"),
+            DEFAULT_DIGITS,
+            &SystemClock
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_digits() {
+        assert_eq!(parse_digits("6").unwrap(), 6);
+        assert_eq!(parse_digits(" 8 ").unwrap(), 8);
+        assert!(parse_digits("5").is_err());
+        assert!(parse_digits("9").is_err());
+        assert!(parse_digits("six").is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("git*", "github"));
+        assert!(glob_match("git*", "git"));
+        assert!(glob_match("*hub", "github"));
+        assert!(glob_match("g?t*b", "github"));
+        assert!(glob_match("work/*/prod", "work/aws/prod"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("git?", "git"));
+        assert!(!glob_match("*lab", "github"));
+        assert!(!glob_match("github", "git"));
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(1_234_567_890), "2009-02-13 23:31:30 UTC");
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1234567890").unwrap(), 1_234_567_890);
+        assert_eq!(
+            parse_timestamp("2009-02-13T23:31:30Z").unwrap(),
+            1_234_567_890
+        );
+        assert_eq!(
+            parse_timestamp("2009-02-14T01:01:30.25+01:30").unwrap(),
+            1_234_567_890
+        );
+        assert_eq!(
+            parse_timestamp("2000-02-29 00:00:00z").unwrap(),
+            951_782_400
+        );
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00-00:00").unwrap(), 0);
+
+        assert!(parse_timestamp("2009-02-13T23:31:30").is_err());
+        assert!(parse_timestamp("2001-02-29T00:00:00Z").is_err());
+        assert!(parse_timestamp("2009-02-13T24:00:00Z").is_err());
+        assert!(parse_timestamp("1970-01-01T00:00:00+01:00").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+    }
 }